@@ -0,0 +1,293 @@
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+
+use chrono::prelude::*;
+use serde_yaml;
+
+/// How a set of excluded days repeats.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Repeat {
+    Never,
+    /// Every year on the same dates, optionally a limited number of times
+    /// or until some date.
+    Yearly {
+        count: Option<u32>,
+        until: Option<NaiveDate>,
+    },
+}
+
+/// A run of one or more days on which tasks shouldn't run, such as a
+/// holiday.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExcludedDays {
+    first: NaiveDate,
+    last: NaiveDate,
+    name: Option<String>,
+    repeat: Repeat,
+}
+
+impl ExcludedDays {
+    fn contains(&self, date: NaiveDate) -> bool {
+        match self.repeat {
+            Repeat::Never => self.first <= date && date <= self.last,
+            Repeat::Yearly { count, until } => {
+                // A range that spans New Year's Day may have started the
+                // year before.
+                let years = date.year() - self.first.year();
+                (years - 1..=years)
+                    .filter(|&years| years >= 0)
+                    .any(|years| {
+                        let shift = |day: NaiveDate| day.with_year(day.year() + years);
+                        match (shift(self.first), shift(self.last)) {
+                            (Some(first), Some(last)) => {
+                                first <= date
+                                    && date <= last
+                                    && count.is_none_or(|count| (years as u32) < count)
+                                    && until.is_none_or(|until| first <= until)
+                            }
+                            // February 29th, in a year that doesn't have one.
+                            _ => false,
+                        }
+                    })
+            }
+        }
+    }
+}
+
+impl fmt::Display for ExcludedDays {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.name {
+            Some(ref name) => f.write_str(name),
+            None if self.first == self.last => write!(f, "{}", self.first),
+            None => write!(f, "{} to {}", self.first, self.last),
+        }
+    }
+}
+
+/// A list of days on which a task shouldn't run, read from either an
+/// iCalendar (.ics) file or a YAML list of dates and date ranges.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Calendar {
+    name: String,
+    days: Vec<ExcludedDays>,
+}
+
+// A date or date range in a YAML calendar, either on its own or with a name.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum YamlEntry {
+    Dates(String),
+    Named { dates: String, name: String },
+}
+
+fn parse_yaml_dates(dates: &str) -> Result<(NaiveDate, NaiveDate), String> {
+    let parse = |date: &str| {
+        NaiveDate::parse_from_str(date.trim(), "%Y-%m-%d")
+            .map_err(|_| format!("\"{}\" is not a date in YYYY-MM-DD form", date.trim()))
+    };
+    match dates.find("..") {
+        Some(dots) => {
+            let (first, last) = (parse(&dates[..dots])?, parse(&dates[dots + 2..])?);
+            if last < first {
+                Err(format!("\"{}\" ends before it starts", dates))
+            } else {
+                Ok((first, last))
+            }
+        }
+        None => parse(dates).map(|date| (date, date)),
+    }
+}
+
+// Reads the date from an iCalendar DATE or DATE-TIME value. For a time,
+// it's the day the time falls on, whatever its time zone.
+fn parse_ics_date(value: &str) -> Option<NaiveDate> {
+    value
+        .get(..8)
+        .and_then(|date| NaiveDate::parse_from_str(date, "%Y%m%d").ok())
+}
+
+fn parse_ics_repeat(rule: &str) -> Result<Repeat, String> {
+    let (mut yearly, mut count, mut until) = (false, None, None);
+    for part in rule.split(';') {
+        let mut pair = part.splitn(2, '=');
+        match (pair.next(), pair.next()) {
+            (Some("FREQ"), Some("YEARLY")) => yearly = true,
+            (Some("COUNT"), Some(value)) => {
+                count = Some(
+                    value
+                        .parse::<u32>()
+                        .map_err(|_| format!("invalid COUNT \"{}\"", value))?,
+                )
+            }
+            (Some("UNTIL"), Some(value)) => {
+                until = Some(
+                    parse_ics_date(value).ok_or_else(|| format!("invalid UNTIL \"{}\"", value))?,
+                )
+            }
+            // These narrow down a yearly rule to the date it started on anyway.
+            (Some("INTERVAL"), Some("1")) | (Some("BYMONTH"), _) | (Some("BYMONTHDAY"), _) => (),
+            _ => return Err(format!("unsupported recurrence rule \"{}\"", rule)),
+        }
+    }
+    if yearly {
+        Ok(Repeat::Yearly { count, until })
+    } else {
+        Err(format!("unsupported recurrence rule \"{}\"", rule))
+    }
+}
+
+// The parts of an iCalendar VEVENT that matter here, as they're read.
+struct IcsEvent {
+    start: Option<NaiveDate>,
+    // The end date, and whether the event ends at the very start of it.
+    end: Option<(NaiveDate, bool)>,
+    summary: Option<String>,
+    repeat: Repeat,
+}
+
+fn unescape_ics_text(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n') | Some('N') => unescaped.push(' '),
+                Some(escaped) => unescaped.push(escaped),
+                None => (),
+            },
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+impl Calendar {
+    /// Reads a calendar file: iCalendar if its name ends in ".ics", and YAML
+    /// otherwise.
+    pub fn load(path: &str) -> Result<Calendar, String> {
+        let mut contents = String::new();
+        File::open(path)
+            .and_then(|mut file| file.read_to_string(&mut contents))
+            .map_err(|e| format!("couldn't read calendar {}: {}", path, e))?;
+        if path.to_ascii_lowercase().ends_with(".ics") {
+            Calendar::from_ics(path, &contents)
+        } else {
+            Calendar::from_yaml(path, &contents)
+        }
+    }
+
+    /// Parses a YAML list whose entries are each a date ("2019-12-25"), a
+    /// range of dates ("2019-12-24..2019-12-26") or a map with `dates` in
+    /// either of those forms and a `name`.
+    pub fn from_yaml(name: &str, yaml: &str) -> Result<Calendar, String> {
+        let entries = serde_yaml::from_str::<Vec<YamlEntry>>(yaml)
+            .map_err(|e| format!("calendar {}: {}", name, e))?;
+        let days = entries
+            .into_iter()
+            .map(|entry| {
+                let (dates, day_name) = match entry {
+                    YamlEntry::Dates(dates) => (dates, None),
+                    YamlEntry::Named { dates, name } => (dates, Some(name)),
+                };
+                parse_yaml_dates(&dates).map(|(first, last)| ExcludedDays {
+                    first,
+                    last,
+                    name: day_name,
+                    repeat: Repeat::Never,
+                })
+            })
+            .collect::<Result<Vec<ExcludedDays>, String>>()
+            .map_err(|e| format!("calendar {}: {}", name, e))?;
+        Ok(Calendar {
+            name: String::from(name),
+            days,
+        })
+    }
+
+    /// Parses the events in an iCalendar file. Each event excludes the days
+    /// it covers, and events that repeat yearly are supported.
+    pub fn from_ics(name: &str, ics: &str) -> Result<Calendar, String> {
+        // Long lines are folded by starting the continuation with a space.
+        let mut lines: Vec<(usize, String)> = Vec::new();
+        for (number, line) in ics.lines().enumerate() {
+            match (line.chars().next(), lines.last_mut()) {
+                (Some(' '), Some(&mut (_, ref mut folded)))
+                | (Some('\t'), Some(&mut (_, ref mut folded))) => folded.push_str(&line[1..]),
+                _ => lines.push((number + 1, String::from(line))),
+            }
+        }
+
+        let mut days = Vec::new();
+        let mut event: Option<IcsEvent> = None;
+        for (number, line) in lines {
+            let error = |e: String| format!("calendar {}: line {}: {}", name, number, e);
+            let colon = match line.find(':') {
+                Some(colon) => colon,
+                None if line.trim().is_empty() => continue,
+                None => return Err(error(format!("\"{}\" is not a property", line))),
+            };
+            let (property, value) = (&line[..colon], &line[colon + 1..]);
+            let mut params = property.split(';');
+            let property = params.next().unwrap().to_ascii_uppercase();
+            match (property.as_str(), event.as_mut()) {
+                ("BEGIN", None) if value.eq_ignore_ascii_case("VEVENT") => {
+                    event = Some(IcsEvent {
+                        start: None,
+                        end: None,
+                        summary: None,
+                        repeat: Repeat::Never,
+                    })
+                }
+                ("END", Some(_)) if value.eq_ignore_ascii_case("VEVENT") => {
+                    let event = event.take().unwrap();
+                    let first = event
+                        .start
+                        .ok_or_else(|| error(String::from("event has no DTSTART")))?;
+                    let last = match event.end {
+                        // An all-day event ends at the start of its end date,
+                        // and so does one that ends at midnight.
+                        Some((end, true)) if end > first => end.pred_opt().unwrap(),
+                        Some((end, _)) if end >= first => end,
+                        Some(_) => return Err(error(String::from("event ends before it starts"))),
+                        None => first,
+                    };
+                    days.push(ExcludedDays {
+                        first,
+                        last,
+                        name: event.summary,
+                        repeat: event.repeat,
+                    });
+                }
+                ("DTSTART", Some(event)) => {
+                    event.start = Some(
+                        parse_ics_date(value)
+                            .ok_or_else(|| error(format!("invalid DTSTART \"{}\"", value)))?,
+                    )
+                }
+                ("DTEND", Some(event)) => {
+                    let end = parse_ics_date(value)
+                        .ok_or_else(|| error(format!("invalid DTEND \"{}\"", value)))?;
+                    let exclusive = value.len() == 8 || value[8..].starts_with("T000000");
+                    event.end = Some((end, exclusive))
+                }
+                ("SUMMARY", Some(event)) => event.summary = Some(unescape_ics_text(value)),
+                ("RRULE", Some(event)) => event.repeat = parse_ics_repeat(value).map_err(error)?,
+                _ => (),
+            }
+        }
+        Ok(Calendar {
+            name: String::from(name),
+            days,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The excluded days that `date` falls in, if it's excluded.
+    pub fn excludes(&self, date: NaiveDate) -> Option<&ExcludedDays> {
+        self.days.iter().find(|days| days.contains(date))
+    }
+}
@@ -0,0 +1,47 @@
+use std::cell::Cell;
+use std::time::Duration;
+
+use chrono::prelude::*;
+
+/// A source of the current time. Scheduling code asks a `Clock` rather
+/// than reading the system time directly, so that tests can control it.
+pub trait Clock {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+/// The real system clock.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to, for tests and schedule previews.
+pub struct ManualClock {
+    now: Cell<DateTime<Utc>>,
+}
+
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> ManualClock {
+        ManualClock {
+            now: Cell::new(now),
+        }
+    }
+
+    pub fn set(&self, now: DateTime<Utc>) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.now
+            .set(self.now.get() + chrono::Duration::from_std(by).unwrap());
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        self.now.get()
+    }
+}
@@ -0,0 +1,69 @@
+use std::fmt;
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use shellwords;
+
+/// The shell that runs commands for tasks with `shell` set, unless they
+/// name another.
+pub const DEFAULT_SHELL: &str = "/bin/sh";
+
+/// A command line, which is split into words like a shell would unless it's
+/// run by the shell, or a list of the program and its arguments.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TaskCommand {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl TaskCommand {
+    /// The program to run and its arguments. With `shell`, a command line
+    /// is passed to `shell_path`, or /bin/sh, with -c.
+    pub fn argv(&self, shell: bool, shell_path: Option<&str>) -> Result<Vec<String>, String> {
+        let argv = match (self, shell) {
+            (TaskCommand::Line(line), true) => vec![
+                String::from(shell_path.unwrap_or(DEFAULT_SHELL)),
+                String::from("-c"),
+                line.clone(),
+            ],
+            (TaskCommand::Line(line), false) => {
+                shellwords::split(line).map_err(|_| String::from("mismatched quotes in cmd"))?
+            }
+            (TaskCommand::Args(_), true) => {
+                return Err(String::from("shell needs cmd to be a string, not a list"))
+            }
+            (TaskCommand::Args(args), false) => args.clone(),
+        };
+        if argv.is_empty() || argv[0].is_empty() {
+            return Err(String::from("cmd is empty"));
+        }
+        Ok(argv)
+    }
+}
+
+struct TaskCommandVisitor;
+
+impl<'de> Visitor<'de> for TaskCommandVisitor {
+    type Value = TaskCommand;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a command line or a list of the program and its arguments")
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<TaskCommand, E> {
+        Ok(TaskCommand::Line(String::from(value)))
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(self, mut seq: A) -> Result<TaskCommand, A::Error> {
+        let mut args = Vec::new();
+        while let Some(arg) = seq.next_element::<String>()? {
+            args.push(arg);
+        }
+        Ok(TaskCommand::Args(args))
+    }
+}
+
+impl<'de> Deserialize<'de> for TaskCommand {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<TaskCommand, D::Error> {
+        deserializer.deserialize_any(TaskCommandVisitor)
+    }
+}
@@ -0,0 +1,210 @@
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem;
+use std::ptr;
+
+use libc;
+
+// The largest buffer to try when looking up a user or group, which is far
+// more than any real entry needs.
+const MAX_LOOKUP_BUFFER: usize = 1 << 20;
+
+struct Passwd {
+    uid: u32,
+    gid: u32,
+    name: CString,
+}
+
+// Looks up a user in the password database, by ID if `user` is a number
+// and by name otherwise.
+fn get_passwd(user: &str) -> Option<Passwd> {
+    let name = CString::new(user).ok()?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut pwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let status = unsafe {
+            match user.parse::<u32>() {
+                Ok(uid) => {
+                    libc::getpwuid_r(uid, &mut pwd, buf.as_mut_ptr(), buf.len(), &mut result)
+                }
+                Err(_) => libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut pwd,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                ),
+            }
+        };
+        if status == libc::ERANGE && buf.len() < MAX_LOOKUP_BUFFER {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if result.is_null() {
+            return None;
+        }
+        return Some(Passwd {
+            uid: pwd.pw_uid,
+            gid: pwd.pw_gid,
+            name: unsafe { CStr::from_ptr(pwd.pw_name) }.to_owned(),
+        });
+    }
+}
+
+// Looks up a group's ID, by ID if `group` is a number and by name
+// otherwise.
+fn get_group_id(group: &str) -> Option<u32> {
+    let name = CString::new(group).ok()?;
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut grp: libc::group = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        let status = unsafe {
+            match group.parse::<u32>() {
+                Ok(gid) => {
+                    libc::getgrgid_r(gid, &mut grp, buf.as_mut_ptr(), buf.len(), &mut result)
+                }
+                Err(_) => libc::getgrnam_r(
+                    name.as_ptr(),
+                    &mut grp,
+                    buf.as_mut_ptr(),
+                    buf.len(),
+                    &mut result,
+                ),
+            }
+        };
+        if status == libc::ERANGE && buf.len() < MAX_LOOKUP_BUFFER {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if result.is_null() {
+            return None;
+        }
+        return Some(grp.gr_gid);
+    }
+}
+
+// The groups a user belongs to, including `gid`.
+fn get_group_list(user: &CStr, gid: u32) -> Vec<u32> {
+    let mut groups: Vec<libc::gid_t> = vec![0; 64];
+    loop {
+        let mut count = groups.len() as libc::c_int;
+        let status =
+            unsafe { libc::getgrouplist(user.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if status >= 0 {
+            groups.truncate(count as usize);
+            return groups;
+        }
+        groups.resize((count as usize).max(groups.len() * 2), 0);
+    }
+}
+
+/// The user and groups to run a command as. Anything that's `None` is left
+/// as it is for periodic itself.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Credentials {
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub groups: Option<Vec<u32>>,
+}
+
+impl Credentials {
+    /// Resolves a user, group and supplementary groups, each given by name or
+    /// numeric ID. Without a group, a user's primary group is used, so a
+    /// user ID that isn't in the password database needs a group. When
+    /// running as root, a user's supplementary groups are also used if none
+    /// are given, so that commands don't keep root's.
+    pub fn resolve(
+        user: Option<&str>,
+        group: Option<&str>,
+        supplementary_groups: Option<&[String]>,
+    ) -> Result<Credentials, String> {
+        let find_group = |group: &str| {
+            get_group_id(group)
+                .or_else(|| group.parse::<u32>().ok())
+                .ok_or_else(|| format!("unknown group \"{}\"", group))
+        };
+        let passwd = match user {
+            Some(user) => match get_passwd(user) {
+                Some(passwd) => Some(passwd),
+                // A user ID that isn't in the password database is fine,
+                // as is a group ID that isn't in the group database.
+                None if user.parse::<u32>().is_ok() => None,
+                None => return Err(format!("unknown user \"{}\"", user)),
+            },
+            None => None,
+        };
+        let uid = match (&passwd, user) {
+            (Some(passwd), _) => Some(passwd.uid),
+            (None, user) => user.and_then(|user| user.parse::<u32>().ok()),
+        };
+        let gid = match (group, &passwd, uid) {
+            (Some(group), _, _) => Some(find_group(group)?),
+            (None, Some(passwd), _) => Some(passwd.gid),
+            // Otherwise the command would keep periodic's own group.
+            (None, None, Some(uid)) => {
+                return Err(format!("user {} has no passwd entry; set group", uid))
+            }
+            (None, None, None) => None,
+        };
+        let groups = match supplementary_groups {
+            Some(groups) => Some(
+                groups
+                    .iter()
+                    .map(|group| find_group(group))
+                    .collect::<Result<Vec<u32>, String>>()?,
+            ),
+            None if uid.is_some() && unsafe { libc::geteuid() } == 0 => Some(match passwd {
+                Some(ref passwd) => get_group_list(&passwd.name, gid.unwrap()),
+                None => gid.into_iter().collect(),
+            }),
+            None => None,
+        };
+        Ok(Credentials { uid, gid, groups })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.uid.is_none() && self.gid.is_none() && self.groups.is_none()
+    }
+
+    /// Checks that periodic is allowed to switch to these credentials. Only
+    /// root can switch to another user or group, or set supplementary groups.
+    pub fn check_privileges(&self) -> Result<(), String> {
+        if unsafe { libc::geteuid() } == 0 {
+            return Ok(());
+        }
+        if let Some(uid) = self.uid.filter(|&uid| uid != unsafe { libc::geteuid() }) {
+            return Err(format!("switching to user ID {} needs root", uid));
+        }
+        if let Some(gid) = self.gid.filter(|&gid| gid != unsafe { libc::getegid() }) {
+            return Err(format!("switching to group ID {} needs root", gid));
+        }
+        if self.groups.is_some() {
+            return Err(String::from("setting supplementary groups needs root"));
+        }
+        Ok(())
+    }
+
+    /// Switches the current process to these credentials. The groups are
+    /// set before the user, while the process can still change them.
+    pub fn apply(&self) -> io::Result<()> {
+        let check = |status: libc::c_int| {
+            if status == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        };
+        if let Some(ref groups) = self.groups {
+            check(unsafe { libc::setgroups(groups.len() as _, groups.as_ptr()) })?;
+        }
+        if let Some(gid) = self.gid {
+            check(unsafe { libc::setgid(gid) })?;
+        }
+        if let Some(uid) = self.uid {
+            check(unsafe { libc::setuid(uid) })?;
+        }
+        Ok(())
+    }
+}
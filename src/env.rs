@@ -0,0 +1,96 @@
+use std::fs::File;
+use std::io::prelude::*;
+
+/// Whether `name` can be used as an environment variable name: letters,
+/// digits and underscores, not starting with a digit.
+pub fn is_valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Reads a value, which may be in single quotes, taken literally, or double
+// quotes, which allow the escapes \n, \", \\ and \$. An unquoted value ends
+// at a " #" comment.
+fn parse_value(value: &str) -> Result<String, String> {
+    let value = value.trim();
+    let quote = match value.chars().next() {
+        Some(quote) if quote == '\'' || quote == '"' => quote,
+        _ => {
+            let value = match value.find(" #") {
+                Some(comment) => &value[..comment],
+                None => value,
+            };
+            return Ok(String::from(value.trim_end()));
+        }
+    };
+    let mut parsed = String::new();
+    let mut chars = value[1..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c == quote => {
+                let rest = chars.as_str().trim_start();
+                return if rest.is_empty() || rest.starts_with('#') {
+                    Ok(parsed)
+                } else {
+                    Err(format!("unexpected \"{}\" after closing quote", rest))
+                };
+            }
+            '\\' if quote == '"' => match chars.next() {
+                Some('n') => parsed.push('\n'),
+                Some(escaped) => parsed.push(escaped),
+                None => break,
+            },
+            _ => parsed.push(c),
+        }
+    }
+    Err(String::from("missing closing quote"))
+}
+
+/// Parses environment variables in dotenv form: one `NAME=value` per line,
+/// optionally starting with `export`, with `#` comments and blank lines
+/// ignored. Values can be quoted.
+pub fn parse_env_file(name: &str, contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = Vec::new();
+    for (number, line) in contents.lines().enumerate() {
+        let error = |e: String| format!("env file {}: line {}: {}", name, number + 1, e);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let equals = line
+            .find('=')
+            .ok_or_else(|| error(format!("\"{}\" is not of the form NAME=value", line)))?;
+        let var = line[..equals].trim();
+        if !is_valid_name(var) {
+            return Err(error(format!("invalid variable name \"{}\"", var)));
+        }
+        let value = parse_value(&line[equals + 1..]).map_err(error)?;
+        vars.push((String::from(var), value));
+    }
+    Ok(vars)
+}
+
+/// Reads and parses an env file.
+pub fn load_env_file(path: &str) -> Result<Vec<(String, String)>, String> {
+    let mut contents = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut contents))
+        .map_err(|e| format!("couldn't read env file {}: {}", path, e))?;
+    parse_env_file(path, &contents)
+}
+
+/// Reads a `NAME=value` pair given on the command line.
+pub fn parse_env_var(var: &str) -> Result<(String, String), String> {
+    match var.find('=') {
+        Some(equals) if is_valid_name(&var[..equals]) => Ok((
+            String::from(&var[..equals]),
+            String::from(&var[equals + 1..]),
+        )),
+        Some(equals) => Err(format!("invalid variable name \"{}\"", &var[..equals])),
+        None => Err(format!("\"{}\" is not of the form NAME=value", var)),
+    }
+}
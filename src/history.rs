@@ -0,0 +1,145 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+/// How many runs are kept for a task unless it says otherwise.
+pub const DEFAULT_HISTORY: usize = 10;
+
+/// How much of a run's output is kept unless its task says otherwise.
+pub const DEFAULT_HISTORY_OUTPUT: &str = "16K";
+
+/// A line of a command's output, and whether it went to "out" or "err".
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct OutputLine {
+    pub stream: String,
+    pub line: String,
+}
+
+/// One invocation of a task's command.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Run {
+    pub pid: u32,
+    pub start: DateTime<Utc>,
+    #[serde(default)]
+    pub end: Option<DateTime<Utc>>,
+    /// How the command exited, such as "exit status 1", once it has.
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub timed_out: bool,
+    /// The end of the command's output, stdout and stderr interleaved.
+    #[serde(default)]
+    pub output: VecDeque<OutputLine>,
+    /// Whether earlier output was dropped to keep within the limit.
+    #[serde(default)]
+    pub output_truncated: bool,
+    #[serde(skip)]
+    output_size: usize,
+}
+
+impl Run {
+    /// How long the command ran for, once it has finished.
+    pub fn duration(&self) -> Option<Duration> {
+        self.end.map(|end| {
+            end.signed_duration_since(self.start)
+                .to_std()
+                .unwrap_or_default()
+        })
+    }
+}
+
+// The last `max` bytes of a line, without splitting a character.
+fn line_tail(line: &str, max: usize) -> &str {
+    let mut start = line.len().saturating_sub(max);
+    while !line.is_char_boundary(start) {
+        start += 1;
+    }
+    &line[start..]
+}
+
+/// The most recent runs of a task, oldest first, each with the last of its
+/// output. Older runs are dropped as new ones start.
+#[derive(Clone, Debug)]
+pub struct History {
+    max_runs: usize,
+    max_output: usize,
+    runs: VecDeque<Run>,
+}
+
+impl History {
+    /// Keeps up to `max_runs` runs, each with up to `max_output` bytes of
+    /// output, counting a newline for each line.
+    pub fn new(max_runs: usize, max_output: usize) -> History {
+        History {
+            max_runs,
+            max_output,
+            runs: VecDeque::new(),
+        }
+    }
+
+    pub fn runs(&self) -> &VecDeque<Run> {
+        &self.runs
+    }
+
+    // The run for a PID that hasn't finished yet.
+    fn running(&mut self, pid: u32) -> Option<&mut Run> {
+        self.runs
+            .iter_mut()
+            .rev()
+            .find(|run| run.pid == pid && run.end.is_none())
+    }
+
+    pub fn start(&mut self, pid: u32, start: DateTime<Utc>) {
+        if self.max_runs == 0 {
+            return;
+        }
+        while self.runs.len() >= self.max_runs {
+            self.runs.pop_front();
+        }
+        self.runs.push_back(Run {
+            pid,
+            start,
+            end: None,
+            status: None,
+            timed_out: false,
+            output: VecDeque::new(),
+            output_truncated: false,
+            output_size: 0,
+        });
+    }
+
+    /// Adds a line of output, dropping the oldest lines of the run if it's
+    /// now over the limit. A line that's over the limit on its own is cut
+    /// down to its end.
+    pub fn add_output(&mut self, pid: u32, stream: &str, line: &str) {
+        let max_output = self.max_output;
+        if max_output == 0 {
+            return;
+        }
+        let run = match self.running(pid) {
+            Some(run) => run,
+            None => return,
+        };
+        let kept = line_tail(line, max_output - 1);
+        run.output_truncated |= kept.len() < line.len();
+        run.output.push_back(OutputLine {
+            stream: String::from(stream),
+            line: String::from(kept),
+        });
+        run.output_size += kept.len() + 1;
+        while run.output_size > max_output {
+            let dropped = run.output.pop_front().unwrap();
+            run.output_size -= dropped.line.len() + 1;
+            run.output_truncated = true;
+        }
+    }
+
+    pub fn finish(&mut self, pid: u32, end: DateTime<Utc>, status: String, timed_out: bool) {
+        if let Some(run) = self.running(pid) {
+            run.end = Some(end);
+            run.status = Some(status);
+            run.timed_out = timed_out;
+        }
+    }
+}
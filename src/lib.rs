@@ -1,5 +1,27 @@
+extern crate bytes;
 extern crate chrono;
+extern crate chrono_tz;
+extern crate flate2;
+extern crate libc;
+extern crate rand;
 extern crate regex;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde;
+extern crate serde_yaml;
+extern crate shellwords;
+extern crate tokio_codec;
 
+pub mod calendar;
+pub mod clock;
+pub mod command;
+pub mod credentials;
+pub mod env;
+pub mod history;
+pub mod limits;
+pub mod output;
+pub mod priority;
+pub mod signal;
 pub mod test;
 pub mod time;
+pub mod timer;
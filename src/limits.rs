@@ -0,0 +1,229 @@
+use std::io;
+
+use libc;
+
+use time::parse_duration;
+
+// How much more CPU time a command gets after SIGXCPU before it's killed.
+const CPU_KILL_GRACE_SECS: libc::rlim_t = 5;
+
+const UNLIMITED: &str = "unlimited";
+
+/// A resource that can be limited for each invocation of a task.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Resource {
+    AddressSpace,
+    Cpu,
+    OpenFiles,
+    Processes,
+    CoreSize,
+    FileSize,
+}
+
+const RESOURCES: [(&str, Resource); 6] = [
+    ("address_space", Resource::AddressSpace),
+    ("cpu", Resource::Cpu),
+    ("open_files", Resource::OpenFiles),
+    ("processes", Resource::Processes),
+    ("core_size", Resource::CoreSize),
+    ("file_size", Resource::FileSize),
+];
+
+impl Resource {
+    pub fn parse(name: &str) -> Result<Resource, String> {
+        RESOURCES
+            .iter()
+            .find(|&&(resource, _)| resource == name)
+            .map(|&(_, resource)| resource)
+            .ok_or_else(|| {
+                format!(
+                    "unknown limit \"{}\", expected address_space, cpu, open_files, \
+                     processes, core_size or file_size",
+                    name
+                )
+            })
+    }
+
+    fn name(self) -> &'static str {
+        RESOURCES
+            .iter()
+            .find(|&&(_, resource)| resource == self)
+            .map(|&(name, _)| name)
+            .unwrap()
+    }
+
+    // Reads a limit, which is "unlimited", or else a size in bytes, a CPU
+    // time or a count depending on the resource.
+    fn parse_value(self, value: &str) -> Result<libc::rlim_t, String> {
+        let value = value.trim();
+        if value == UNLIMITED {
+            return Ok(libc::RLIM_INFINITY);
+        }
+        let parsed = match self {
+            Resource::AddressSpace | Resource::CoreSize | Resource::FileSize => parse_size(value),
+            Resource::Cpu => parse_duration(value)
+                .map_err(|e| e.to_string())
+                // A partial second still counts as one.
+                .map(|cpu| cpu.as_secs() + u64::from(cpu.subsec_nanos() > 0)),
+            Resource::OpenFiles | Resource::Processes => value
+                .parse::<u64>()
+                .map_err(|_| format!("\"{}\" is not a number", value)),
+        };
+        parsed.map_err(|e| format!("invalid {} limit: {}", self.name(), e))
+    }
+
+    // The soft and hard limits to set for a value. The hard CPU time limit
+    // is a little higher, so that a command is sent SIGXCPU and has a
+    // chance to exit cleanly before it's killed.
+    fn soft_and_hard(self, value: libc::rlim_t) -> (libc::rlim_t, libc::rlim_t) {
+        match self {
+            Resource::Cpu if value != libc::RLIM_INFINITY => {
+                (value, value.saturating_add(CPU_KILL_GRACE_SECS))
+            }
+            _ => (value, value),
+        }
+    }
+
+    fn getrlimit(self) -> io::Result<libc::rlimit> {
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        let status = unsafe {
+            match self {
+                Resource::AddressSpace => libc::getrlimit(libc::RLIMIT_AS, &mut limit),
+                Resource::Cpu => libc::getrlimit(libc::RLIMIT_CPU, &mut limit),
+                Resource::OpenFiles => libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit),
+                Resource::Processes => libc::getrlimit(libc::RLIMIT_NPROC, &mut limit),
+                Resource::CoreSize => libc::getrlimit(libc::RLIMIT_CORE, &mut limit),
+                Resource::FileSize => libc::getrlimit(libc::RLIMIT_FSIZE, &mut limit),
+            }
+        };
+        if status == 0 {
+            Ok(limit)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+
+    fn setrlimit(self, limit: &libc::rlimit) -> io::Result<()> {
+        let status = unsafe {
+            match self {
+                Resource::AddressSpace => libc::setrlimit(libc::RLIMIT_AS, limit),
+                Resource::Cpu => libc::setrlimit(libc::RLIMIT_CPU, limit),
+                Resource::OpenFiles => libc::setrlimit(libc::RLIMIT_NOFILE, limit),
+                Resource::Processes => libc::setrlimit(libc::RLIMIT_NPROC, limit),
+                Resource::CoreSize => libc::setrlimit(libc::RLIMIT_CORE, limit),
+                Resource::FileSize => libc::setrlimit(libc::RLIMIT_FSIZE, limit),
+            }
+        };
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+/// Parses a number of bytes, optionally followed by K, M, G or T (with or
+/// without a trailing "B" or "iB") for multiples of 1024.
+pub fn parse_size(value: &str) -> Result<u64, String> {
+    let value = value.trim();
+    let digits = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let invalid = || format!("\"{}\" is not a size such as \"512M\" or \"2G\"", value);
+    let number = value[..digits].parse::<u64>().map_err(|_| invalid())?;
+    let unit = value[digits..].trim().to_ascii_uppercase();
+    let unit = unit
+        .strip_suffix("IB")
+        .or_else(|| unit.strip_suffix('B'))
+        .unwrap_or(&unit);
+    let shift = match unit {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(invalid()),
+    };
+    number
+        .checked_mul(1 << shift)
+        .ok_or_else(|| format!("size \"{}\" is too large", value))
+}
+
+/// Resource limits for a task's commands, set with setrlimit in each child
+/// before it runs its command.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Limits {
+    limits: Vec<(Resource, libc::rlim_t)>,
+}
+
+impl Limits {
+    /// Sets the limit named `name` to `value`.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let resource = Resource::parse(name)?;
+        let value = resource.parse_value(value)?;
+        self.limits.retain(|&(other, _)| other != resource);
+        self.limits.push((resource, value));
+        Ok(())
+    }
+
+    /// The limit set for `resource`, or `None` if it's left as it is.
+    pub fn get(&self, resource: Resource) -> Option<libc::rlim_t> {
+        self.limits
+            .iter()
+            .find(|&&(other, _)| other == resource)
+            .map(|&(_, value)| value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    /// Checks that the limits can be set, since only root can raise a hard
+    /// limit.
+    pub fn check(&self) -> Result<(), String> {
+        if unsafe { libc::geteuid() } == 0 {
+            return Ok(());
+        }
+        for &(resource, value) in &self.limits {
+            let current = resource
+                .getrlimit()
+                .map_err(|e| format!("couldn't read the {} limit: {}", resource.name(), e))?;
+            let (_, hard) = resource.soft_and_hard(value);
+            if current.rlim_max != libc::RLIM_INFINITY
+                && (hard == libc::RLIM_INFINITY || hard > current.rlim_max)
+            {
+                return Err(format!(
+                    "raising the {} limit above {} needs root",
+                    resource.name(),
+                    current.rlim_max
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the soft and hard limits for the current process. Raising a
+    /// hard limit fails unless the process is privileged.
+    pub fn apply(&self) -> io::Result<()> {
+        for &(resource, value) in &self.limits {
+            let (soft, hard) = resource.soft_and_hard(value);
+            resource.setrlimit(&libc::rlimit {
+                rlim_cur: soft,
+                rlim_max: hard,
+            })?;
+        }
+        Ok(())
+    }
+}
+
+/// Explains a signal that's sent when a process exceeds a limit.
+pub fn limit_signal_reason(signal: i32) -> Option<&'static str> {
+    match signal {
+        libc::SIGXCPU => Some("CPU time limit exceeded"),
+        libc::SIGXFSZ => Some("file size limit exceeded"),
+        _ => None,
+    }
+}
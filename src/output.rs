@@ -0,0 +1,252 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, prelude::*};
+use std::path::Path;
+
+use bytes::BytesMut;
+use chrono::{DateTime, Local, NaiveDate};
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tokio_codec::Decoder;
+
+use limits::parse_size;
+
+// How many rotated log files are kept unless a task says otherwise.
+const DEFAULT_KEEP: usize = 5;
+
+// Longer lines are split, so that a command that never writes a newline
+// can't make periodic buffer everything it writes.
+const MAX_LINE_LENGTH: usize = 64 * 1024;
+
+/// Splits a command's output into lines. Unlike `LinesCodec`, output that
+/// isn't valid UTF-8 is kept, with the invalid bytes replaced, and a last
+/// line that doesn't end in a newline is still a line.
+#[derive(Debug, Default)]
+pub struct LineCodec;
+
+fn to_line(bytes: &[u8]) -> String {
+    let bytes = match bytes.last() {
+        Some(b'\r') => &bytes[..bytes.len() - 1],
+        _ => bytes,
+    };
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+impl Decoder for LineCodec {
+    type Item = String;
+    type Error = io::Error;
+
+    fn decode(&mut self, buf: &mut BytesMut) -> Result<Option<String>, io::Error> {
+        if let Some(newline) = buf.iter().position(|&b| b == b'\n') {
+            let line = buf.split_to(newline + 1);
+            return Ok(Some(to_line(&line[..newline])));
+        }
+        if buf.len() >= MAX_LINE_LENGTH {
+            return Ok(Some(to_line(&buf.split_to(MAX_LINE_LENGTH))));
+        }
+        Ok(None)
+    }
+
+    fn decode_eof(&mut self, buf: &mut BytesMut) -> Result<Option<String>, io::Error> {
+        match self.decode(buf)? {
+            Some(line) => Ok(Some(line)),
+            None if buf.is_empty() => Ok(None),
+            None => Ok(Some(to_line(&buf.take()))),
+        }
+    }
+}
+
+/// When a log file is rotated, and what happens to the old ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rotation {
+    /// Rotate before a line would take the file past this many bytes.
+    pub max_size: Option<u64>,
+    /// Rotate when the first line of a new day is written.
+    pub daily: bool,
+    /// How many rotated files to keep.
+    pub keep: usize,
+    /// Whether to gzip rotated files.
+    pub compress: bool,
+}
+
+impl Default for Rotation {
+    fn default() -> Rotation {
+        Rotation {
+            max_size: None,
+            daily: false,
+            keep: DEFAULT_KEEP,
+            compress: false,
+        }
+    }
+}
+
+impl Rotation {
+    /// Sets the setting named `name` to `value`.
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let parse_bool = |value: &str| match value {
+            "true" => Ok(true),
+            "false" => Ok(false),
+            _ => Err(format!("{} must be true or false", name)),
+        };
+        match name {
+            "max_size" => {
+                self.max_size =
+                    Some(parse_size(value).map_err(|e| format!("invalid max_size: {}", e))?)
+            }
+            "daily" => self.daily = parse_bool(value)?,
+            "keep" => {
+                self.keep = value
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid keep \"{}\", expected a number", value))?
+            }
+            "compress" => self.compress = parse_bool(value)?,
+            _ => {
+                return Err(format!(
+                "unknown log_rotation setting \"{}\", expected max_size, daily, keep or compress",
+                name
+            ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Fills in a log file path template, where "{name}" is replaced by the task
+/// name and "{date}" by the date, as YYYY-MM-DD.
+pub fn log_file_path(template: &str, task_name: &str, date: NaiveDate) -> String {
+    template
+        .replace("{name}", &task_name.replace('/', "_"))
+        .replace("{date}", &date.format("%Y-%m-%d").to_string())
+}
+
+// The name of the `number`th most recently rotated file.
+fn rotated_path(path: &str, number: usize, compressed: bool) -> String {
+    if compressed {
+        format!("{}.{}.gz", path, number)
+    } else {
+        format!("{}.{}", path, number)
+    }
+}
+
+fn gzip(path: &str) -> io::Result<()> {
+    let mut encoder = GzEncoder::new(
+        File::create(format!("{}.gz", path))?,
+        Compression::default(),
+    );
+    io::copy(&mut File::open(path)?, &mut encoder)?;
+    encoder.finish()?;
+    fs::remove_file(path)
+}
+
+/// A task's log file, which is reopened when its path's date changes and
+/// rotated as its `Rotation` says.
+#[derive(Debug)]
+pub struct LogFile {
+    template: String,
+    task_name: String,
+    rotation: Option<Rotation>,
+    path: String,
+    file: File,
+    size: u64,
+    opened_on: NaiveDate,
+}
+
+impl LogFile {
+    pub fn open(
+        template: &str,
+        task_name: &str,
+        rotation: Option<Rotation>,
+        today: NaiveDate,
+    ) -> io::Result<LogFile> {
+        let path = log_file_path(template, task_name, today);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let metadata = file.metadata()?;
+        // A file that was last written to on an earlier day is due for daily
+        // rotation.
+        let opened_on = match metadata.modified() {
+            Ok(modified) if metadata.len() > 0 => {
+                DateTime::<Local>::from(modified).date_naive().min(today)
+            }
+            _ => today,
+        };
+        Ok(LogFile {
+            template: String::from(template),
+            task_name: String::from(task_name),
+            rotation,
+            path,
+            file,
+            size: metadata.len(),
+            opened_on,
+        })
+    }
+
+    /// The path currently being written to.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    fn reopen(&mut self, today: NaiveDate) -> io::Result<()> {
+        *self = LogFile::open(&self.template, &self.task_name, self.rotation.take(), today)?;
+        Ok(())
+    }
+
+    // Shifts each rotated file up by one, dropping the oldest, and makes the
+    // current file the first of them.
+    fn rotate(&mut self) -> io::Result<()> {
+        let rotation = self.rotation.clone().unwrap_or_default();
+        let remove = |path: &str| match fs::remove_file(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            result => result,
+        };
+        if rotation.keep == 0 {
+            remove(&self.path)?;
+        } else {
+            remove(&rotated_path(&self.path, rotation.keep, false))?;
+            remove(&rotated_path(&self.path, rotation.keep, true))?;
+            for number in (1..rotation.keep).rev() {
+                for &compressed in &[false, true] {
+                    let from = rotated_path(&self.path, number, compressed);
+                    if Path::new(&from).exists() {
+                        fs::rename(&from, rotated_path(&self.path, number + 1, compressed))?;
+                    }
+                }
+            }
+            let first = rotated_path(&self.path, 1, false);
+            fs::rename(&self.path, &first)?;
+            if rotation.compress {
+                gzip(&first)?;
+            }
+        }
+        let today = self.opened_on;
+        self.reopen(today)
+    }
+
+    /// Appends a line, first moving on to a new file if the date in the
+    /// path has changed or the file needs rotating.
+    pub fn write_line(&mut self, line: &str, today: NaiveDate) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+        if today != self.opened_on {
+            let daily = self
+                .rotation
+                .as_ref()
+                .is_some_and(|rotation| rotation.daily);
+            if log_file_path(&self.template, &self.task_name, today) != self.path {
+                self.reopen(today)?;
+            } else if daily && self.size > 0 {
+                self.rotate()?;
+                self.opened_on = today;
+            } else {
+                self.opened_on = today;
+            }
+        }
+        let max_size = self
+            .rotation
+            .as_ref()
+            .and_then(|rotation| rotation.max_size);
+        if max_size.is_some_and(|max_size| self.size > 0 && self.size + length > max_size) {
+            self.rotate()?;
+        }
+        writeln!(self.file, "{}", line)?;
+        self.size += length;
+        Ok(())
+    }
+}
@@ -1,53 +1,447 @@
-extern crate clap;
 extern crate chrono;
+extern crate clap;
 extern crate futures;
+extern crate libc;
 extern crate periodic;
 extern crate serde;
 extern crate serde_yaml;
+extern crate tokio_codec;
 extern crate tokio_core;
+extern crate tokio_io;
 extern crate tokio_process;
 extern crate tokio_signal;
 
-use std::collections::HashMap;
-use std::error::Error;
-use std::fs::File;
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CStr;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io::ErrorKind;
+use std::os::unix::process::{CommandExt as UnixCommandExt, ExitStatusExt};
 use std::path::Path;
-use std::process::{Command, ExitStatus};
+use std::process::{Command, ExitStatus, Stdio};
 use std::rc::Rc;
 use std::str;
 use std::sync::RwLock;
 use std::time::Duration;
 
-use clap::{crate_authors, crate_version, App, Arg, ArgMatches};
-use chrono::prelude::*;
-use futures::{future, Future, Stream};
+use chrono::{DateTime, Local, Utc};
+use clap::{crate_authors, crate_version, App, AppSettings, Arg, ArgMatches, SubCommand};
+use futures::future::Either;
+use futures::sync::oneshot;
+use futures::{future, stream, Future, Stream};
 #[macro_use]
 extern crate serde_derive;
+use periodic::calendar::Calendar;
+use periodic::clock::{Clock, ManualClock, SystemClock};
+use periodic::command::TaskCommand;
+use periodic::credentials::Credentials;
+use periodic::env::{is_valid_name, load_env_file, parse_env_var};
+use periodic::history::{History, Run, DEFAULT_HISTORY, DEFAULT_HISTORY_OUTPUT};
+use periodic::limits::{limit_signal_reason, parse_size, Limits};
+use periodic::output::{LineCodec, LogFile, Rotation};
+use periodic::priority::{CpuSet, IoClass, Priority};
+use periodic::signal::{parse_signal, signal_name};
+use periodic::time::{
+    hashed_offset, parse_date_time_in, parse_duration, preview_runs, random_offset, Alignment,
+    CatchUp, CronSchedule, DueRuns, EndTime, PreviewEntry, Recurrence, RunConditions, Schedule,
+    StartSpec, TimeWindow, Zone,
+};
+use periodic::timer::{TaskTimer, Tick};
+use serde::de::{self, Visitor};
 use serde::{Deserialize, Deserializer};
+use tokio_codec::FramedRead;
 use tokio_core::reactor::{Core, Handle, Interval, Timeout};
-use tokio_process::CommandExt;
-use tokio_signal::unix::{Signal, SIGTERM, SIGUSR1, SIGUSR2};
+use tokio_process::{Child, CommandExt};
+use tokio_signal::unix::{Signal, SIGHUP, SIGINT, SIGTERM, SIGUSR1, SIGUSR2};
 
-const DEFAULT_CONTROL_FILE: &'static str = "./control.yaml";
-const DEFAULT_INTERVAL_SECS: &'static str = "5";
-const DEFAULT_MAX_CONCURRENT: &'static str = "1";
-const DEFAULT_NAME: &'static str = "periodic task";
+const DEFAULT_CONTROL_FILE: &str = "./control.yaml";
+const DEFAULT_STATUS_FILE: &str = "./status.yaml";
+const DEFAULT_INTERVAL: &str = "5";
+const DEFAULT_KILL_GRACE: &str = "10s";
+const DEFAULT_MAX_CONCURRENT: &str = "1";
+const DEFAULT_NAME: &str = "periodic task";
+const DEFAULT_PREVIEW_COUNT: &str = "10";
+const DEFAULT_SCHEDULE_MODE: &str = "fixed_rate";
+// How long to keep reading a command's captured output after it exits,
+// in case something it started still has the pipes open.
+const OUTPUT_DRAIN_MILLIS: u64 = 1000;
 
 #[derive(Debug, Deserialize)]
 struct PeriodicTask {
     #[serde(default = "default_name")]
     name: String,
-    #[serde(default = "default_interval_secs")]
-    interval_secs: u64,
+    #[serde(
+        default = "default_interval",
+        alias = "interval_secs",
+        deserialize_with = "duration_from_config"
+    )]
+    interval: Duration,
+    #[serde(default, deserialize_with = "schedule_from_config")]
+    schedule: Option<CronSchedule>,
+    #[serde(default)]
+    align: bool,
+    #[serde(default, deserialize_with = "align_to_from_config")]
+    align_to: Option<Alignment>,
+    #[serde(default = "default_schedule_mode")]
+    schedule_mode: ScheduleMode,
+    #[serde(default, deserialize_with = "start_time_from_config")]
+    start_time: Option<StartSpec>,
+    #[serde(default, deserialize_with = "zone_from_config")]
+    timezone: Option<Zone>,
+    #[serde(default, deserialize_with = "optional_duration_from_config")]
+    start_splay: Option<Duration>,
+    #[serde(default, deserialize_with = "optional_duration_from_config")]
+    jitter: Option<Duration>,
+    #[serde(default = "default_jitter_mode")]
+    jitter_mode: JitterMode,
+    #[serde(
+        default = "default_catch_up",
+        deserialize_with = "catch_up_from_config"
+    )]
+    catch_up: CatchUp,
+    #[serde(default, deserialize_with = "windows_from_config")]
+    active_windows: Vec<TimeWindow>,
+    #[serde(default, deserialize_with = "windows_from_config")]
+    blackout_windows: Vec<TimeWindow>,
+    #[serde(default, deserialize_with = "calendar_from_config")]
+    exclude_calendar: Option<Calendar>,
+    #[serde(default)]
+    max_runs: Option<u32>,
+    #[serde(default, deserialize_with = "end_time_from_config")]
+    end_time: Option<EndTime>,
+    #[serde(default, deserialize_with = "optional_duration_from_config")]
+    run_for: Option<Duration>,
     #[serde(default = "default_max_concurrent")]
     max_concurrent: u32,
-    #[serde(deserialize_with = "cmd_from_config")]
-    cmd: Vec<String>,
+    #[serde(default, deserialize_with = "optional_duration_from_config")]
+    timeout: Option<Duration>,
+    #[serde(
+        default = "default_timeout_signal",
+        deserialize_with = "signal_from_config"
+    )]
+    timeout_signal: i32,
+    #[serde(
+        default = "default_kill_grace",
+        deserialize_with = "duration_from_config"
+    )]
+    kill_grace: Duration,
+    #[serde(
+        default = "default_stop_signal",
+        deserialize_with = "signal_from_config"
+    )]
+    stop_signal: i32,
+    #[serde(default = "default_process_group")]
+    process_group: bool,
+    #[serde(default)]
+    cwd: Option<String>,
+    #[serde(default, deserialize_with = "env_from_config")]
+    env: Vec<(String, String)>,
+    #[serde(default, deserialize_with = "env_file_from_config")]
+    env_file: Vec<(String, String)>,
+    #[serde(default)]
+    clear_env: bool,
+    #[serde(default, deserialize_with = "name_or_id_from_config")]
+    user: Option<String>,
+    #[serde(default, deserialize_with = "name_or_id_from_config")]
+    group: Option<String>,
+    #[serde(default, deserialize_with = "names_or_ids_from_config")]
+    supplementary_groups: Option<Vec<String>>,
+    #[serde(skip)]
+    credentials: Credentials,
+    #[serde(default, deserialize_with = "limits_from_config")]
+    limits: Limits,
+    #[serde(default)]
+    nice: Option<i32>,
+    #[serde(default, deserialize_with = "io_class_from_config")]
+    io_class: Option<IoClass>,
+    #[serde(default)]
+    io_priority: Option<u8>,
+    #[serde(default, deserialize_with = "cpu_affinity_from_config")]
+    cpu_affinity: Option<CpuSet>,
+    #[serde(default)]
+    output: Option<OutputMode>,
+    #[serde(default)]
+    log_file: Option<String>,
+    #[serde(default, deserialize_with = "log_rotation_from_config")]
+    log_rotation: Option<Rotation>,
+    #[serde(skip)]
+    log_writer: Option<Rc<RefCell<LogFile>>>,
+    #[serde(default = "default_history")]
+    history: usize,
+    #[serde(
+        default = "default_history_output",
+        deserialize_with = "size_from_config"
+    )]
+    history_output: usize,
+    #[serde(default)]
+    shell: bool,
+    #[serde(default)]
+    shell_path: Option<String>,
+    cmd: TaskCommand,
+}
+
+impl PeriodicTask {
+    fn validate(&self, clock: &dyn Clock) -> Result<(), String> {
+        if self.schedule.is_none() && self.interval == Duration::from_secs(0) {
+            return Err(format!(
+                "\"{}\": interval must be greater than zero",
+                self.name
+            ));
+        }
+        if let Some(ref schedule) = self.schedule {
+            if schedule.next_after(clock.now()).is_none() {
+                return Err(format!("\"{}\": schedule never matches", self.name));
+            }
+        }
+        if self.schedule.is_some() && self.alignment().is_some() {
+            return Err(format!(
+                "\"{}\": align and align_to can't be used with schedule",
+                self.name
+            ));
+        }
+        if self.timeout == Some(Duration::from_secs(0)) {
+            return Err(format!(
+                "\"{}\": timeout must be greater than zero",
+                self.name
+            ));
+        }
+        if self.max_runs == Some(0) {
+            return Err(format!(
+                "\"{}\": max_runs must be greater than zero",
+                self.name
+            ));
+        }
+        if self.run_for == Some(Duration::from_secs(0)) {
+            return Err(format!(
+                "\"{}\": run_for must be greater than zero",
+                self.name
+            ));
+        }
+        self.argv()?;
+        match (self.output_mode(), &self.log_file) {
+            (OutputMode::file, None) => {
+                return Err(format!("\"{}\": output: file needs a log_file", self.name))
+            }
+            (OutputMode::file, Some(_)) => (),
+            (_, None) if self.log_rotation.is_some() => {
+                return Err(format!("\"{}\": log_rotation needs a log_file", self.name))
+            }
+            (_, None) => (),
+            (_, Some(_)) => {
+                return Err(format!(
+                    "\"{}\": log_file can only be used with output: file",
+                    self.name
+                ))
+            }
+        }
+        self.priority()
+            .validate()
+            .map_err(|e| format!("\"{}\": {}", self.name, e))?;
+        if let Some(ref cwd) = self.cwd {
+            validate_cwd(cwd.clone()).map_err(|e| format!("\"{}\": cwd {}", self.name, e))?;
+        }
+        Ok(())
+    }
+
+    // Looks up the user and groups the task runs as, which periodic must be
+    // allowed to switch to.
+    fn resolve_credentials(&mut self) -> Result<(), String> {
+        self.credentials = Credentials::resolve(
+            self.user.as_deref(),
+            self.group.as_deref(),
+            self.supplementary_groups.as_deref(),
+        )
+        .map_err(|e| format!("\"{}\": {}", self.name, e))?;
+        Ok(())
+    }
+
+    // Checks that periodic is allowed to run the task as it asks, which
+    // mostly depends on whether it's running as root. This is left out of
+    // validate so that a schedule can be previewed by anyone.
+    fn check_privileges(&self) -> Result<(), String> {
+        self.credentials
+            .check_privileges()
+            .and_then(|_| self.limits.check())
+            .and_then(|_| self.priority().check())
+            .map_err(|e| format!("\"{}\": {}", self.name, e))
+    }
+
+    // Output goes to the log file if there is one, and is inherited from
+    // periodic otherwise.
+    fn output_mode(&self) -> OutputMode {
+        match (self.output, &self.log_file) {
+            (Some(output), _) => output,
+            (None, Some(_)) => OutputMode::file,
+            (None, None) => OutputMode::inherit,
+        }
+    }
+
+    fn open_log_file(&mut self) -> Result<(), String> {
+        if let Some(ref template) = self.log_file {
+            let log_file = LogFile::open(
+                template,
+                &self.name,
+                self.log_rotation.clone(),
+                Local::now().date_naive(),
+            )
+            .map_err(|e| format!("\"{}\": couldn't open {}: {}", self.name, template, e))?;
+            self.log_writer = Some(Rc::new(RefCell::new(log_file)));
+        }
+        Ok(())
+    }
+
+    fn history(&self) -> History {
+        History::new(self.history, self.history_output)
+    }
+
+    fn priority(&self) -> Priority {
+        Priority {
+            nice: self.nice,
+            io_class: self.io_class,
+            io_priority: self.io_priority,
+            cpu_affinity: self.cpu_affinity.clone(),
+        }
+    }
+
+    // The program to run and its arguments.
+    fn argv(&self) -> Result<Vec<String>, String> {
+        self.cmd
+            .argv(self.shell, self.shell_path.as_deref())
+            .map_err(|e| format!("\"{}\": {}", self.name, e))
+    }
+
+    // Settings a task leaves out are taken from the command line.
+    fn apply_defaults(&mut self, defaults: &TaskDefaults) {
+        if self.start_time.is_none() {
+            self.start_time = defaults.start_time;
+        }
+        if self.timezone.is_none() {
+            self.timezone = Some(defaults.timezone);
+        }
+        if self.max_runs.is_none() {
+            self.max_runs = defaults.max_runs;
+        }
+        if self.end_time.is_none() {
+            self.end_time = defaults.end_time;
+        }
+    }
+
+    // An interval is aligned to the clock if the task asks for that, to
+    // the start of the day unless it says otherwise.
+    fn alignment(&self) -> Option<Alignment> {
+        match self.align_to {
+            Some(alignment) => Some(alignment),
+            None if self.align => Some(Alignment::Day),
+            None => None,
+        }
+    }
+
+    fn recurrence(&self) -> Recurrence {
+        match (&self.schedule, self.alignment()) {
+            (Some(schedule), _) => Recurrence::Cron(schedule.clone()),
+            (None, Some(alignment)) => Recurrence::Aligned(self.interval, alignment),
+            (None, None) => Recurrence::Interval(self.interval),
+        }
+    }
+
+    fn run_conditions(&self) -> RunConditions<'_> {
+        RunConditions {
+            active_windows: &self.active_windows,
+            blackout_windows: &self.blackout_windows,
+            exclude_calendar: self.exclude_calendar.as_ref(),
+        }
+    }
+
+    fn zone(&self) -> Zone {
+        self.timezone.unwrap_or(Zone::Local)
+    }
+
+    fn start_delay(&self, clock: &dyn Clock) -> Duration {
+        match self.start_time {
+            Some(start_time) => start_time.delay(&self.zone().now(clock)),
+            None => Duration::from_secs(0),
+        }
+    }
+
+    // When a task that starts at `start` should stop, if it should.
+    fn deadline(&self, start: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let end_time = self.end_time.map(|end_time| {
+            end_time
+                .after(&start.with_timezone(&self.zone()))
+                .with_timezone(&Utc)
+        });
+        let run_for = self
+            .run_for
+            .and_then(|run_for| chrono::Duration::from_std(run_for).ok())
+            .and_then(|run_for| start.checked_add_signed(run_for));
+        match (end_time, run_for) {
+            (Some(end_time), Some(run_for)) => Some(end_time.min(run_for)),
+            (end_time, run_for) => end_time.or(run_for),
+        }
+    }
+}
+
+// What happens to a command's stdout and stderr. With inherit, they're
+// periodic's own. With prefix, each line is written to periodic's stdout
+// or stderr with the time, task name, PID and stream in front, and with
+// file, it's appended to the task's log file with the time, PID and
+// stream in front. With discard, output is thrown away. Output that's
+// prefixed or written to the log file is also kept in the task's history.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[allow(non_camel_case_types)]
+enum OutputMode {
+    inherit,
+    prefix,
+    discard,
+    file,
+}
+
+// Where the lines of a command's captured output go.
+#[derive(Clone)]
+struct OutputSink {
+    task_name: String,
+    pid: u32,
+    log_writer: Option<Rc<RefCell<LogFile>>>,
+    task_db: Rc<TaskStateDb>,
+}
+
+impl OutputSink {
+    fn write_line(&self, stream: &str, line: &str) {
+        self.task_db
+            .record_output(&self.task_name, self.pid, stream, line);
+        let now = Local::now();
+        let time = now.format("%Y-%m-%d %H:%M:%S");
+        match self.log_writer {
+            Some(ref log_writer) => {
+                let line = format!("{} PID {} {}: {}", time, self.pid, stream, line);
+                if let Err(e) = log_writer.borrow_mut().write_line(&line, now.date_naive()) {
+                    println!("\"{}\": couldn't write to log file: {}", self.task_name, e);
+                }
+            }
+            None if stream == "err" => eprintln!(
+                "{} \"{}\" PID {} {}: {}",
+                time, self.task_name, self.pid, stream, line
+            ),
+            None => println!(
+                "{} \"{}\" PID {} {}: {}",
+                time, self.task_name, self.pid, stream, line
+            ),
+        }
+    }
+}
+
+// Task settings given on the command line, which also apply to tasks from
+// a file that don't specify them.
+struct TaskDefaults {
+    start_time: Option<StartSpec>,
+    timezone: Zone,
+    max_runs: Option<u32>,
+    end_time: Option<EndTime>,
 }
 
-#[derive(Clone, Copy, Deserialize, PartialEq)]
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 #[allow(non_camel_case_types)]
 enum TaskMode {
     run,
@@ -55,83 +449,565 @@ enum TaskMode {
     stop,
 }
 
-fn cmd_from_config<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+// With fixed_rate, invocations start on every tick regardless of whether
+// earlier ones are still running. With fixed_delay, the next tick is only
+// scheduled once the previous invocation has finished.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[allow(non_camel_case_types)]
+enum ScheduleMode {
+    fixed_rate,
+    fixed_delay,
+}
+
+impl ScheduleMode {
+    fn from_arg(value: &str) -> ScheduleMode {
+        match value {
+            "fixed_delay" => ScheduleMode::fixed_delay,
+            _ => ScheduleMode::fixed_rate,
+        }
+    }
+}
+
+// How the offsets for start_splay and jitter are chosen. With hashed, the
+// offset is derived from the task name and host name, so each replica of
+// a task always lands on the same offset.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[allow(non_camel_case_types)]
+enum JitterMode {
+    random,
+    hashed,
+}
+
+impl JitterMode {
+    fn offset(self, task_name: &str, max: Duration) -> Duration {
+        match self {
+            JitterMode::random => random_offset(max),
+            JitterMode::hashed => hashed_offset(&format!("{}@{}", task_name, hostname()), max),
+        }
+    }
+}
+
+fn hostname() -> String {
+    let mut buf = [0 as libc::c_char; 256];
+    unsafe {
+        if libc::gethostname(buf.as_mut_ptr(), buf.len() - 1) != 0 {
+            return String::new();
+        }
+        CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+    }
+}
+
+fn env_from_config<'de, D>(deserializer: D) -> Result<Vec<(String, String)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    use serde_yaml::Value;
+    BTreeMap::<String, Value>::deserialize(deserializer).and_then(|vars| {
+        vars.into_iter()
+            .map(|(name, value)| {
+                if !is_valid_name(&name) {
+                    return Err(Error::custom(format!("invalid variable name \"{}\"", name)));
+                }
+                let value = match value {
+                    Value::String(value) => value,
+                    Value::Number(value) => value.to_string(),
+                    Value::Bool(value) => value.to_string(),
+                    Value::Null => String::new(),
+                    _ => {
+                        return Err(Error::custom(format!(
+                            "the value of {} must be a string, number or boolean",
+                            name
+                        )))
+                    }
+                };
+                Ok((name, value))
+            })
+            .collect()
+    })
+}
+
+fn name_or_id<E: de::Error>(value: serde_yaml::Value) -> Result<String, E> {
+    match value {
+        serde_yaml::Value::String(name) => Ok(name),
+        serde_yaml::Value::Number(id) => Ok(id.to_string()),
+        _ => Err(E::custom("expected a name or a numeric ID")),
+    }
+}
+
+fn name_or_id_from_config<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    serde_yaml::Value::deserialize(deserializer)
+        .and_then(name_or_id)
+        .map(Some)
+}
+
+fn names_or_ids_from_config<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<serde_yaml::Value>::deserialize(deserializer).and_then(|values| {
+        values
+            .into_iter()
+            .map(name_or_id)
+            .collect::<Result<Vec<String>, D::Error>>()
+            .map(Some)
+    })
+}
+
+fn limits_from_config<'de, D>(deserializer: D) -> Result<Limits, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    use serde_yaml::Value;
+    BTreeMap::<String, Value>::deserialize(deserializer).and_then(|values| {
+        let mut limits = Limits::default();
+        for (name, value) in values {
+            let value = match value {
+                Value::String(value) => value,
+                Value::Number(value) => value.to_string(),
+                _ => {
+                    return Err(Error::custom(format!(
+                        "the {} limit must be a string or a number",
+                        name
+                    )))
+                }
+            };
+            limits.set(&name, &value).map_err(Error::custom)?;
+        }
+        Ok(limits)
+    })
+}
+
+fn log_rotation_from_config<'de, D>(deserializer: D) -> Result<Option<Rotation>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    use serde_yaml::Value;
+    BTreeMap::<String, Value>::deserialize(deserializer).and_then(|values| {
+        let mut rotation = Rotation::default();
+        for (name, value) in values {
+            let value = match value {
+                Value::String(value) => value,
+                Value::Number(value) => value.to_string(),
+                Value::Bool(value) => value.to_string(),
+                _ => {
+                    return Err(Error::custom(format!(
+                        "{} must be a string, number or boolean",
+                        name
+                    )))
+                }
+            };
+            rotation.set(&name, &value).map_err(Error::custom)?;
+        }
+        Ok(Some(rotation))
+    })
+}
+
+fn size_from_config<'de, D>(deserializer: D) -> Result<usize, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    match serde_yaml::Value::deserialize(deserializer)? {
+        serde_yaml::Value::String(size) => parse_size(&size),
+        serde_yaml::Value::Number(size) => parse_size(&size.to_string()),
+        _ => Err(String::from("expected a size such as \"16K\"")),
+    }
+    .map(|size| size as usize)
+    .map_err(Error::custom)
+}
+
+fn io_class_from_config<'de, D>(deserializer: D) -> Result<Option<IoClass>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    String::deserialize(deserializer)
+        .and_then(|class| IoClass::parse(&class).map(Some).map_err(Error::custom))
+}
+
+// A CPU list such as "0-3,6", or a YAML list of CPU numbers.
+fn cpu_affinity_from_config<'de, D>(deserializer: D) -> Result<Option<CpuSet>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    use serde_yaml::Value;
+    match Value::deserialize(deserializer)? {
+        Value::String(cpus) => CpuSet::parse(&cpus),
+        Value::Number(cpu) => CpuSet::parse(&cpu.to_string()),
+        Value::Sequence(cpus) => cpus
+            .iter()
+            .map(|cpu| {
+                cpu.as_u64()
+                    .map(|cpu| cpu as usize)
+                    .ok_or_else(|| String::from("CPUs must be numbers"))
+            })
+            .collect::<Result<Vec<usize>, String>>()
+            .and_then(CpuSet::from_cpus),
+        _ => Err(String::from(
+            "expected a CPU list such as \"0-3,6\" or a list of CPU numbers",
+        )),
+    }
+    .map(Some)
+    .map_err(Error::custom)
+}
+
+fn env_file_from_config<'de, D>(deserializer: D) -> Result<Vec<(String, String)>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    String::deserialize(deserializer).and_then(|path| load_env_file(&path).map_err(Error::custom))
+}
+
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a number of seconds or a duration such as \"1h30m\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Duration, E> {
+        Ok(Duration::from_secs(value))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Duration, E> {
+        if value < 0 {
+            Err(E::custom(format!("negative duration: {}", value)))
+        } else {
+            Ok(Duration::from_secs(value as u64))
+        }
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Duration, E> {
+        parse_duration(value).map_err(E::custom)
+    }
+}
+
+fn duration_from_config<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(DurationVisitor)
+}
+
+fn optional_duration_from_config<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_any(DurationVisitor).map(Some)
+}
+
+fn signal_from_config<'de, D>(deserializer: D) -> Result<i32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    String::deserialize(deserializer)
+        .and_then(|string| parse_signal(&string).map_err(Error::custom))
+}
+
+fn schedule_from_config<'de, D>(deserializer: D) -> Result<Option<CronSchedule>, D::Error>
 where
     D: Deserializer<'de>,
 {
     use serde::de::Error;
     String::deserialize(deserializer).and_then(|string| {
-        shellwords::split(&string).map_err(|_| Error::custom("Mismatched quotes"))
+        CronSchedule::parse(&string)
+            .map(Some)
+            .map_err(Error::custom)
+    })
+}
+
+fn align_to_from_config<'de, D>(deserializer: D) -> Result<Option<Alignment>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    String::deserialize(deserializer)
+        .and_then(|string| Alignment::parse(&string).map(Some).map_err(Error::custom))
+}
+
+fn zone_from_config<'de, D>(deserializer: D) -> Result<Option<Zone>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    String::deserialize(deserializer)
+        .and_then(|string| Zone::parse(&string).map(Some).map_err(Error::custom))
+}
+
+fn catch_up_from_config<'de, D>(deserializer: D) -> Result<CatchUp, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    String::deserialize(deserializer)
+        .and_then(|string| CatchUp::parse(&string).map_err(Error::custom))
+}
+
+fn start_time_from_config<'de, D>(deserializer: D) -> Result<Option<StartSpec>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    String::deserialize(deserializer)
+        .and_then(|string| StartSpec::parse(&string).map(Some).map_err(Error::custom))
+}
+
+fn end_time_from_config<'de, D>(deserializer: D) -> Result<Option<EndTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    String::deserialize(deserializer)
+        .and_then(|string| EndTime::parse(&string).map(Some).map_err(Error::custom))
+}
+
+fn calendar_from_config<'de, D>(deserializer: D) -> Result<Option<Calendar>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    String::deserialize(deserializer)
+        .and_then(|path| Calendar::load(&path).map(Some).map_err(Error::custom))
+}
+
+fn windows_from_config<'de, D>(deserializer: D) -> Result<Vec<TimeWindow>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    use serde::de::Error;
+    Vec::<String>::deserialize(deserializer).and_then(|specs| {
+        specs
+            .iter()
+            .map(|spec| TimeWindow::parse(spec).map_err(Error::custom))
+            .collect()
     })
 }
 
 struct TaskState {
     pub concurrent_count: u32,
     pub mode: TaskMode,
+    pub runs: u32,
+    pub finished: bool,
+    pub stop_signal: i32,
+    pub process_group: bool,
+    pub history: History,
 }
 
 impl TaskState {
-    fn new() -> TaskState {
+    fn new(stop_signal: i32, process_group: bool, history: History) -> TaskState {
         TaskState {
             concurrent_count: 0,
             mode: TaskMode::run,
+            runs: 0,
+            finished: false,
+            stop_signal,
+            process_group,
+            history,
+        }
+    }
+
+    fn status(&self) -> TaskStatus {
+        TaskStatus {
+            mode: self.mode,
+            finished: self.finished,
+            running: self.concurrent_count,
+            runs: self.runs,
+            history: self.history.runs().iter().cloned().collect(),
+        }
+    }
+}
+
+// A task's entry in the status file.
+#[derive(Deserialize, Serialize)]
+struct TaskStatus {
+    mode: TaskMode,
+    finished: bool,
+    running: u32,
+    runs: u32,
+    history: Vec<Run>,
+}
+
+// A running child, which leads its own process group unless its task
+// opted out of that.
+#[derive(Clone)]
+struct ActiveProcess {
+    task_name: String,
+    pid: u32,
+    process_group: bool,
+    stop_signal: i32,
+}
+
+impl ActiveProcess {
+    fn signal(&self, signal: i32) {
+        send_signal(self.pid, self.process_group, signal);
+    }
+}
+
+impl fmt::Display for ActiveProcess {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.process_group {
+            write!(f, "process group {}", self.pid)
+        } else {
+            write!(f, "PID {}", self.pid)
         }
     }
 }
 
 struct TaskStateDb {
-    propagate_sigterm_to_children: bool,
+    clock: Rc<dyn Clock>,
+    propagate_signals: bool,
+    shutdown_timeout: Option<Duration>,
+    status_file: Option<String>,
+    // Whether anything in the status file is out of date.
+    status_changed: Cell<bool>,
     tasks: RwLock<HashMap<String, TaskState>>,
-    active_pids: RwLock<Vec<u32>>,
+    active_pids: RwLock<Vec<ActiveProcess>>,
 }
 
 impl TaskStateDb {
-    fn new() -> TaskStateDb {
+    fn new(
+        clock: Rc<dyn Clock>,
+        propagate_signals: bool,
+        shutdown_timeout: Option<Duration>,
+        status_file: Option<String>,
+    ) -> TaskStateDb {
         TaskStateDb {
-            propagate_sigterm_to_children: false,
+            clock,
+            propagate_signals,
+            shutdown_timeout,
+            status_file,
+            status_changed: Cell::new(true),
             tasks: RwLock::new(HashMap::new()),
             active_pids: RwLock::new(Vec::new()),
         }
     }
 
-    fn add_new_task(&self, task_name: &str) {
+    fn add_new_task(
+        &self,
+        task_name: &str,
+        stop_signal: i32,
+        process_group: bool,
+        history: History,
+    ) {
         let mut tasks_mut = self.tasks.write().unwrap();
-        tasks_mut.insert(task_name.to_string(), TaskState::new());
+        tasks_mut.insert(
+            task_name.to_string(),
+            TaskState::new(stop_signal, process_group, history),
+        );
     }
 
-    fn set_all_task_modes(&self, mode: TaskMode) {
-        let mut tasks_mut = self.tasks.write().unwrap();
-        for ref mut task in tasks_mut.values_mut() {
-            task.mode = mode
-        }
-        if mode == TaskMode::stop {
-            let active_pids = self.active_pids.read().unwrap().clone();
-            if self.propagate_sigterm_to_children {
-                for pid in active_pids.into_iter() {
-                    println!("sending SIGTERM to {}", pid);
-                }
-            } else {
+    // Returns the processes that were running if the tasks were stopped.
+    fn set_all_task_modes(&self, mode: TaskMode) -> Vec<ActiveProcess> {
+        {
+            let mut tasks_mut = self.tasks.write().unwrap();
+            for task in tasks_mut.values_mut().filter(|task| !task.finished) {
+                task.mode = mode
+            }
+        }
+        self.status_changed.set(true);
+        match mode {
+            TaskMode::stop => self.stop_processes(None),
+            TaskMode::run | TaskMode::pause => Vec::new(),
+        }
+    }
+
+    // Sends a stopped task's running processes, or every task's without a
+    // name, their stop signal if signals are propagated. Returns the
+    // processes, for the shutdown timeout.
+    fn stop_processes(&self, task_name: Option<&str>) -> Vec<ActiveProcess> {
+        let stopping = self
+            .active_pids
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|process| task_name.is_none_or(|name| process.task_name == name))
+            .cloned()
+            .collect::<Vec<ActiveProcess>>();
+        if stopping.is_empty() {
+            return stopping;
+        }
+        if self.propagate_signals {
+            for process in &stopping {
                 println!(
-                    "waiting for PID(s): {}",
-                    active_pids
-                        .into_iter()
-                        .map(|pid| format!("{}", pid))
-                        .collect::<Vec<String>>()
-                        .join(", ")
+                    "sending {} to {}",
+                    signal_name(process.stop_signal).unwrap_or("signal"),
+                    process
                 );
+                process.signal(process.stop_signal);
             }
+        } else {
+            println!(
+                "waiting for PID(s): {}",
+                stopping
+                    .iter()
+                    .map(|process| format!("{}", process.pid))
+                    .collect::<Vec<String>>()
+                    .join(", ")
+            );
         }
+        stopping
     }
 
-    fn set_task_modes_from_control_file(&self) {
+    // Used when stopped children are still running once the shutdown
+    // timeout is up.
+    fn kill_remaining_processes(&self, stopped: &[ActiveProcess]) {
+        for process in self
+            .active_pids
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|process| stopped.iter().any(|stopped| stopped.pid == process.pid))
+        {
+            println!("shutdown timeout reached, sending SIGKILL to {}", process);
+            process.signal(libc::SIGKILL);
+        }
+    }
+
+    // Returns the processes of any tasks the control file stopped.
+    fn set_task_modes_from_control_file(&self) -> Vec<ActiveProcess> {
+        let mut stopped_tasks = Vec::new();
         if let Some(control_tasks) = read_control_file(DEFAULT_CONTROL_FILE) {
             let mut tasks_mut = self.tasks.write().unwrap();
             for (task_name, task_mode) in control_tasks.iter() {
                 if let Some(task) = tasks_mut.get_mut(task_name) {
-                    task.mode = (*task_mode).clone();
+                    if !task.finished && task.mode != *task_mode {
+                        task.mode = *task_mode;
+                        self.status_changed.set(true);
+                        if *task_mode == TaskMode::stop {
+                            stopped_tasks.push(task_name.clone());
+                        }
+                    }
                 }
             }
         }
+        stopped_tasks
+            .iter()
+            .flat_map(|task_name| self.stop_processes(Some(task_name)))
+            .collect()
+    }
+
+    // Stops a task for good once it has reached its limits. Unlike a stop
+    // from a signal or the control file, this can't be undone.
+    fn finish_task(&self, task_name: &str, reason: &str) {
+        let mut tasks_mut = self.tasks.write().unwrap();
+        let task = tasks_mut.get_mut(task_name).unwrap();
+        if !task.finished {
+            println!("\"{}\" {}, stopping", task_name, reason);
+            task.finished = true;
+            task.mode = TaskMode::stop;
+            self.status_changed.set(true);
+        }
     }
 
     fn get_task_mode(&self, task_name: &str) -> TaskMode {
@@ -162,32 +1038,88 @@ impl TaskStateDb {
 
     fn start_process(&self, task_name: &str, pid: u32) {
         println!("PID {} started for {}", pid, task_name);
-        self.active_pids.write().unwrap().push(pid);
+        let mut tasks_mut = self.tasks.write().unwrap();
+        let task = tasks_mut.get_mut(task_name).unwrap();
+        self.active_pids.write().unwrap().push(ActiveProcess {
+            task_name: task_name.to_string(),
+            pid,
+            process_group: task.process_group,
+            stop_signal: task.stop_signal,
+        });
+        task.runs += 1;
+        task.history.start(pid, self.clock.now());
+        self.status_changed.set(true);
     }
 
-    fn finish_process(&self, task_name: &str, terminated_pid: u32, status: ExitStatus) {
+    fn finish_process(
+        &self,
+        task_name: &str,
+        terminated_pid: u32,
+        status: ExitStatus,
+        timed_out: bool,
+    ) {
         let mut tasks_mut = self.tasks.write().unwrap();
         let task = tasks_mut.get_mut(task_name).unwrap();
 
         let mut active_pids_mut = self.active_pids.write().unwrap();
-        active_pids_mut.retain(|pid| *pid != terminated_pid);
+        active_pids_mut.retain(|process| process.pid != terminated_pid);
 
         task.concurrent_count -= 1;
-        let status_msg = match status.code() {
-            Some(code) => format!(" (exit status {})", code),
-            None => format!(" (terminated by signal)"),
-        };
+        let status_msg = describe_exit_status(status);
         println!(
-            "\"{}\": PID {} terminated{}{}",
+            "\"{}\": PID {} {} ({}){}",
             task_name,
             terminated_pid,
+            if timed_out {
+                "timed out, terminated"
+            } else {
+                "terminated"
+            },
             status_msg,
             if task.concurrent_count > 0 {
                 format!(", {} still running", task.concurrent_count)
             } else {
-                format!("")
+                String::new()
             }
         );
+        task.history
+            .finish(terminated_pid, self.clock.now(), status_msg, timed_out);
+        self.status_changed.set(true);
+    }
+
+    fn record_output(&self, task_name: &str, pid: u32, stream: &str, line: &str) {
+        let mut tasks_mut = self.tasks.write().unwrap();
+        let task = tasks_mut.get_mut(task_name).unwrap();
+        task.history.add_output(pid, stream, line);
+        self.status_changed.set(true);
+    }
+
+    // Writes every task's mode, counts and history to the status file, if
+    // there is one and it's out of date. It's written to a temporary file
+    // first, so that it's never seen half written.
+    fn write_status_file(&self) {
+        let path = match self.status_file {
+            Some(ref path) if self.status_changed.replace(false) => path,
+            _ => return,
+        };
+        let statuses = self
+            .tasks
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, task)| (name.clone(), task.status()))
+            .collect::<BTreeMap<String, TaskStatus>>();
+        let temp_path = format!("{}.tmp", path);
+        let written = serde_yaml::to_string(&statuses)
+            .map_err(|e| e.to_string())
+            .and_then(|yaml| {
+                fs::write(&temp_path, yaml)
+                    .and_then(|_| fs::rename(&temp_path, path))
+                    .map_err(|e| e.to_string())
+            });
+        if let Err(e) = written {
+            println!("couldn't write status file {}: {}", path, e);
+        }
     }
 
     fn cleanup_failed_process(&self, task_name: &str) {
@@ -197,9 +1129,9 @@ impl TaskStateDb {
     }
 
     fn count_runnable(&self) -> usize {
-        let tasks_mut = self.tasks.write().unwrap();
-        let mut runnable = tasks_mut.len();
-        for &ref task in tasks_mut.values() {
+        let tasks = self.tasks.read().unwrap();
+        let mut runnable = tasks.len();
+        for task in tasks.values() {
             if task.concurrent_count == 0 && task.mode == TaskMode::stop {
                 runnable -= 1;
             }
@@ -211,12 +1143,52 @@ impl TaskStateDb {
 fn default_name() -> String {
     String::from(DEFAULT_NAME)
 }
-fn default_interval_secs() -> u64 {
-    DEFAULT_INTERVAL_SECS.parse::<u64>().unwrap()
+fn default_interval() -> Duration {
+    parse_duration(DEFAULT_INTERVAL).unwrap()
+}
+fn default_schedule_mode() -> ScheduleMode {
+    ScheduleMode::from_arg(DEFAULT_SCHEDULE_MODE)
+}
+fn default_jitter_mode() -> JitterMode {
+    JitterMode::random
+}
+fn default_catch_up() -> CatchUp {
+    CatchUp::Once
+}
+fn default_timeout_signal() -> i32 {
+    SIGTERM
+}
+fn default_process_group() -> bool {
+    true
+}
+fn default_stop_signal() -> i32 {
+    SIGTERM
+}
+fn default_kill_grace() -> Duration {
+    parse_duration(DEFAULT_KILL_GRACE).unwrap()
 }
 fn default_max_concurrent() -> u32 {
     DEFAULT_MAX_CONCURRENT.parse::<u32>().unwrap()
 }
+fn default_history() -> usize {
+    DEFAULT_HISTORY
+}
+fn default_history_output() -> usize {
+    parse_size(DEFAULT_HISTORY_OUTPUT).unwrap() as usize
+}
+
+// Describes how a child exited, such as "exit status 1".
+fn describe_exit_status(status: ExitStatus) -> String {
+    match (status.code(), status.signal()) {
+        (Some(code), _) => format!("exit status {}", code),
+        (None, Some(signal)) => match (signal_name(signal), limit_signal_reason(signal)) {
+            (Some(name), Some(reason)) => format!("{}, {}", name, reason),
+            (Some(name), None) => format!("terminated by {}", name),
+            (None, _) => format!("terminated by signal {}", signal),
+        },
+        (None, None) => String::from("terminated by signal"),
+    }
+}
 
 fn read_control_file(list_filename: &str) -> Option<HashMap<String, TaskMode>> {
     let list_path = Path::new(list_filename);
@@ -225,20 +1197,20 @@ fn read_control_file(list_filename: &str) -> Option<HashMap<String, TaskMode>> {
     } else {
         match File::open(list_path) {
             Err(err) => {
-                println!("couldn't open {:?} ({})", list_path, err.description());
+                println!("couldn't open {:?} ({})", list_path, err);
                 None
             }
             Ok(mut file) => {
                 let mut yaml = String::new();
                 match file.read_to_string(&mut yaml) {
                     Err(err) => {
-                        println!("couldn't read {:?}: {}", list_path, err.description());
+                        println!("couldn't read {:?}: {}", list_path, err);
                         None
                     }
                     Ok(_) => match serde_yaml::from_str::<HashMap<String, TaskMode>>(&yaml) {
                         Ok(task_names) => Some(task_names),
                         Err(e) => {
-                            println!("{}", e.description());
+                            println!("{}", e);
                             None
                         }
                     },
@@ -248,6 +1220,18 @@ fn read_control_file(list_filename: &str) -> Option<HashMap<String, TaskMode>> {
     }
 }
 
+// Kills any of the stopped processes that are still running once the
+// shutdown timeout, if there is one, is up.
+fn start_shutdown_timeout(task_db: &Rc<TaskStateDb>, stopped: Vec<ActiveProcess>, handle: &Handle) {
+    if let (false, Some(timeout)) = (stopped.is_empty(), task_db.shutdown_timeout) {
+        let task_db = task_db.clone();
+        handle.spawn(Timeout::new(timeout, handle).unwrap().then(move |_| {
+            task_db.kill_remaining_processes(&stopped);
+            Ok(())
+        }));
+    }
+}
+
 fn get_signal_future(
     task_db: Rc<TaskStateDb>,
     signum: i32,
@@ -259,7 +1243,19 @@ fn get_signal_future(
             .flatten_stream()
             .for_each(move |signal| {
                 println!("signal {} received", signal);
-                task_db.set_all_task_modes(mode);
+                let stopped = task_db.set_all_task_modes(mode);
+                // An interrupt or hangup from the terminal only reaches
+                // periodic's own process group, so unless the invocations
+                // have just been sent their stop signal, it's passed on to
+                // the ones in groups of their own, as it would have reached
+                // them otherwise.
+                if (signal == SIGINT || signal == SIGHUP) && !task_db.propagate_signals {
+                    for process in stopped.iter().filter(|process| process.process_group) {
+                        println!("sending {} to {}", signal_name(signal).unwrap(), process);
+                        process.signal(signal);
+                    }
+                }
+                start_shutdown_timeout(&task_db, stopped, &handle);
                 Ok(())
             }),
     )
@@ -271,35 +1267,228 @@ fn get_monitor_future(
 ) -> Box<dyn Future<Item = (), Error = std::io::Error>> {
     let interval = Interval::new(Duration::from_secs(1), &handle).unwrap();
     Box::new(interval.for_each(move |_| {
-        task_db.set_task_modes_from_control_file();
+        let stopped = task_db.set_task_modes_from_control_file();
+        start_shutdown_timeout(&task_db, stopped, &handle);
+        task_db.write_status_file();
         match task_db.count_runnable() {
             0 => {
                 println!("exiting, all tasks have finished");
-                return Err(std::io::Error::new(ErrorKind::Interrupted, "done"));
+                Err(std::io::Error::new(ErrorKind::Interrupted, "done"))
             }
             _ => Ok(()),
         }
     }))
 }
 
-fn invoke_command(task: &PeriodicTask, task_db: &Rc<TaskStateDb>, handle: &Handle) {
+// Resolves once the invocation (if one was started) has finished.
+type InvocationFuture = Box<dyn Future<Item = (), Error = ()>>;
+
+// Resolves to a child's exit status, and whether it timed out.
+type ChildFuture = Box<dyn Future<Item = (ExitStatus, bool), Error = std::io::Error>>;
+
+// Signals a child, or everything in the process group it leads.
+fn send_signal(pid: u32, process_group: bool, signal: i32) {
+    let target = if process_group {
+        -(pid as libc::pid_t)
+    } else {
+        pid as libc::pid_t
+    };
+    unsafe { libc::kill(target, signal) };
+}
+
+// Waits for a child to exit. One that runs for longer than its task's
+// timeout is sent the task's timeout signal and then, if it still hasn't
+// exited after the grace period, SIGKILL. Since the child isn't reaped
+// until it's been waited for, its PID can't have been reused by then.
+fn wait_for_child(child: Child, task: &PeriodicTask, handle: &Handle) -> ChildFuture {
+    let timeout = match task.timeout {
+        Some(timeout) => timeout,
+        None => return Box::new(child.map(|status| (status, false))),
+    };
+    let pid = child.id();
+    let (task_name, signal, kill_grace) = (task.name.clone(), task.timeout_signal, task.kill_grace);
+    let process_group = task.process_group;
+    let handle = handle.clone();
+    Box::new(
+        child
+            .select2(Timeout::new(timeout, &handle).unwrap())
+            .map_err(|e| e.split().0)
+            .and_then(move |finished| -> ChildFuture {
+                let child = match finished {
+                    Either::A((status, _)) => return Box::new(future::ok((status, false))),
+                    Either::B((_, child)) => child,
+                };
+                println!(
+                    "\"{}\": PID {} timed out after {:?}, sending {}",
+                    task_name,
+                    pid,
+                    timeout,
+                    signal_name(signal).unwrap_or("signal")
+                );
+                send_signal(pid, process_group, signal);
+                Box::new(
+                    child
+                        .select2(Timeout::new(kill_grace, &handle).unwrap())
+                        .map_err(|e| e.split().0)
+                        .and_then(move |finished| -> ChildFuture {
+                            let child = match finished {
+                                Either::A((status, _)) => {
+                                    return Box::new(future::ok((status, true)))
+                                }
+                                Either::B((_, child)) => child,
+                            };
+                            println!(
+                                "\"{}\": PID {} still running after {:?}, sending SIGKILL",
+                                task_name, pid, kill_grace
+                            );
+                            send_signal(pid, process_group, libc::SIGKILL);
+                            Box::new(child.map(|status| (status, true)))
+                        }),
+                )
+            }),
+    )
+}
+
+fn forward_lines<R>(reader: R, stream: &'static str, sink: OutputSink) -> InvocationFuture
+where
+    R: tokio_io::AsyncRead + 'static,
+{
+    Box::new(
+        FramedRead::new(reader, LineCodec)
+            .for_each(move |line| {
+                sink.write_line(stream, &line);
+                Ok(())
+            })
+            .map_err(|_| ()),
+    )
+}
+
+// Reads a child's piped stdout and stderr in the background. The returned
+// receiver completes once both have been closed.
+fn capture_output(child: &mut Child, sink: OutputSink, handle: &Handle) -> oneshot::Receiver<()> {
+    let (sender, receiver) = oneshot::channel();
+    let stdout: InvocationFuture = match child.stdout().take() {
+        Some(stdout) => forward_lines(stdout, "out", sink.clone()),
+        None => Box::new(future::ok(())),
+    };
+    let stderr: InvocationFuture = match child.stderr().take() {
+        Some(stderr) => forward_lines(stderr, "err", sink),
+        None => Box::new(future::ok(())),
+    };
+    handle.spawn(stdout.join(stderr).then(move |_| {
+        let _ = sender.send(());
+        Ok(())
+    }));
+    receiver
+}
+
+// Waits a little while for captured output to be read to the end.
+fn wait_for_output(output: Option<oneshot::Receiver<()>>, handle: &Handle) -> InvocationFuture {
+    match output {
+        Some(output) => Box::new(
+            output
+                .select2(Timeout::new(Duration::from_millis(OUTPUT_DRAIN_MILLIS), handle).unwrap())
+                .then(|_| Ok(())),
+        ),
+        None => Box::new(future::ok(())),
+    }
+}
+
+fn invoke_command(
+    task: &PeriodicTask,
+    timer: &Rc<RefCell<TaskTimer>>,
+    task_db: &Rc<TaskStateDb>,
+    handle: &Handle,
+) -> InvocationFuture {
     if task_db.init_process_if_allowed(&task.name, task.max_concurrent) {
         let task_db_clone = task_db.clone();
-        let (cmd_name, cmd_args) = (task.cmd[0].clone(), task.cmd[1..].into_iter());
+        let argv = task.argv().unwrap();
+        let (cmd_name, cmd_args) = (argv[0].clone(), argv[1..].iter());
         let task_name = task.name.clone();
-        match Command::new(cmd_name).args(cmd_args).spawn_async(&handle) {
-            Ok(command) => {
+        let mut command = Command::new(cmd_name);
+        command.args(cmd_args);
+        if task.clear_env {
+            command.env_clear();
+        }
+        // Variables set directly on the task take precedence over its env file.
+        command.envs(
+            task.env_file
+                .iter()
+                .chain(task.env.iter())
+                .map(|(name, value)| (name, value)),
+        );
+        if let Some(ref cwd) = task.cwd {
+            command.current_dir(cwd);
+        }
+        // The hook runs in the forked child just before it execs the command,
+        // where only async-signal-safe calls may be made, so the apply
+        // functions stick to system calls and don't allocate. Limits and
+        // priorities are set first, while the child may still be privileged
+        // enough to raise them.
+        let priority = task.priority();
+        if !task.limits.is_empty() || !priority.is_empty() || !task.credentials.is_empty() {
+            let (limits, credentials) = (task.limits.clone(), task.credentials.clone());
+            unsafe {
+                command.pre_exec(move || {
+                    limits.apply()?;
+                    priority.apply()?;
+                    credentials.apply()
+                })
+            };
+        }
+        if task.process_group {
+            command.process_group(0);
+        }
+        // Only output that periodic writes out itself is read, and kept in
+        // the task's history; inherited output goes straight through.
+        let output_mode = task.output_mode();
+        let capture = match output_mode {
+            OutputMode::inherit => false,
+            OutputMode::discard => {
+                command.stdout(Stdio::null()).stderr(Stdio::null());
+                false
+            }
+            OutputMode::prefix | OutputMode::file => {
+                command.stdout(Stdio::piped()).stderr(Stdio::piped());
+                true
+            }
+        };
+        match command.spawn_async(handle) {
+            Ok(mut command) => {
                 let pid = command.id();
                 task_db_clone.start_process(&task_name, pid);
-                handle.spawn(
-                    command
-                        .map(move |status| (task_name, task_db_clone, pid, status))
+                if let Some(reason) = timer.borrow_mut().started() {
+                    task_db_clone.finish_task(&task_name, &reason);
+                }
+                let output = if capture {
+                    Some(capture_output(
+                        &mut command,
+                        OutputSink {
+                            task_name: task_name.clone(),
+                            pid,
+                            log_writer: task.log_writer.clone(),
+                            task_db: task_db_clone.clone(),
+                        },
+                        handle,
+                    ))
+                } else {
+                    None
+                };
+                let handle = handle.clone();
+                return Box::new(
+                    wait_for_child(command, task, &handle)
+                        .and_then(move |finished| {
+                            wait_for_output(output, &handle).then(move |_| Ok(finished))
+                        })
+                        .map(move |(status, timed_out)| {
+                            (task_name, task_db_clone, pid, status, timed_out)
+                        })
                         .then(|args| {
-                            let (task_name, task_db, pid, status) = args.unwrap();
-                            task_db.finish_process(&task_name, pid, status);
+                            let (task_name, task_db, pid, status, timed_out) = args.unwrap();
+                            task_db.finish_process(&task_name, pid, status, timed_out);
                             future::ok(())
                         }),
-                )
+                );
             }
             Err(e) => {
                 println!("couldn't start \"{}\": {}", task.name, e);
@@ -307,6 +1496,112 @@ fn invoke_command(task: &PeriodicTask, task_db: &Rc<TaskStateDb>, handle: &Handl
             }
         }
     }
+    Box::new(future::ok(()))
+}
+
+type TickStream = Box<dyn Stream<Item = Tick, Error = std::io::Error>>;
+
+// Produces what the task's timer says to do each time it's checked, waiting
+// as long as it says in between, and ends once the task is finished. The
+// stream is only polled for its next tick once the previous one has been
+// handled, so a schedule restarted by the handler is picked up straight
+// away.
+fn get_tick_stream(
+    timer: Rc<RefCell<TaskTimer>>,
+    clock: Rc<dyn Clock>,
+    handle: &Handle,
+) -> TickStream {
+    let handle = handle.clone();
+    let ticks = stream::unfold(false, move |finished| {
+        if finished {
+            return None;
+        }
+        let tick = timer.borrow_mut().tick(&*clock);
+        let next: Box<dyn Future<Item = (Option<Tick>, bool), Error = std::io::Error>> = match tick
+        {
+            Tick::Wait(delay) => {
+                Box::new(Timeout::new(delay, &handle).unwrap().map(|_| (None, false)))
+            }
+            Tick::Finished(_) => Box::new(future::ok((Some(tick), true))),
+            Tick::Run { .. } => Box::new(future::ok((Some(tick), false))),
+        };
+        Some(next)
+    });
+    Box::new(ticks.filter_map(|tick| tick))
+}
+
+fn run_task(
+    task: &PeriodicTask,
+    timer: &Rc<RefCell<TaskTimer>>,
+    task_db: &Rc<TaskStateDb>,
+    handle: &Handle,
+) -> InvocationFuture {
+    match task_db.get_task_mode(&task.name) {
+        TaskMode::run => {
+            let skip_reason = timer
+                .borrow()
+                .skip_reason(&task.run_conditions(), &*task_db.clock);
+            match skip_reason {
+                Some(reason) => {
+                    println!("\"{}\" is {}", task.name, reason);
+                    Box::new(future::ok(()))
+                }
+                None => invoke_command(task, timer, task_db, handle),
+            }
+        }
+        TaskMode::pause => {
+            println!("\"{}\" is paused", task.name);
+            Box::new(future::ok(()))
+        }
+        TaskMode::stop => Box::new(future::ok(())),
+    }
+}
+
+// Runs a task `runs` times for the runs that came due at a tick.
+fn run_due(
+    task: &Rc<PeriodicTask>,
+    timer: &Rc<RefCell<TaskTimer>>,
+    task_db: &Rc<TaskStateDb>,
+    handle: &Handle,
+    due: &DueRuns,
+    runs: u32,
+) -> InvocationFuture {
+    if due.missed > 0 {
+        if runs > 0 {
+            println!(
+                "\"{}\" missed {} scheduled run(s), catching up with {}",
+                task.name, due.missed, runs
+            );
+        } else {
+            println!(
+                "\"{}\" missed {} scheduled run(s), not catching up",
+                task.name, due.missed
+            );
+        }
+    }
+    let jitter = task
+        .jitter
+        .map(|max| task.jitter_mode.offset(&task.name, max));
+    // Catch-up runs follow one another rather than starting together, and
+    // only the first waits for the jitter.
+    let (task, timer, task_db, handle) =
+        (task.clone(), timer.clone(), task_db.clone(), handle.clone());
+    Box::new(
+        stream::iter_ok(0..runs).for_each(move |run| -> InvocationFuture {
+            match jitter {
+                Some(delay) if run == 0 && delay > Duration::from_secs(0) => {
+                    let (task, timer, task_db, handle) =
+                        (task.clone(), timer.clone(), task_db.clone(), handle.clone());
+                    Box::new(
+                        Timeout::new(delay, &handle)
+                            .unwrap()
+                            .then(move |_| run_task(&task, &timer, &task_db, &handle)),
+                    )
+                }
+                _ => run_task(&task, &timer, &task_db, &handle),
+            }
+        }),
+    )
 }
 
 fn get_task_future(
@@ -315,105 +1610,329 @@ fn get_task_future(
     handle: Handle,
     start_delay: Duration,
 ) -> Box<dyn Future<Item = (), Error = std::io::Error>> {
-    let start_timeout: Timeout = Timeout::new(start_delay, &handle).unwrap();
+    let start_delay = match task.start_splay {
+        Some(max) => start_delay + task.jitter_mode.offset(&task.name, max),
+        None => start_delay,
+    };
 
-    task_db.add_new_task(&task.name);
+    task_db.add_new_task(
+        &task.name,
+        task.stop_signal,
+        task.process_group,
+        task.history(),
+    );
+    let task = Rc::new(task);
 
     if start_delay.as_secs() > 0 {
-        println!("starting in {}", start_delay.as_secs());
-    }
-    Box::new(start_timeout.and_then(|_| {
-        invoke_command(&task, &task_db, &handle);
-        let interval = Interval::new(Duration::from_secs(task.interval_secs), &handle).unwrap();
-        interval.for_each(move |_| {
-            match task_db.get_task_mode(&task.name) {
-                TaskMode::run => invoke_command(&task, &task_db, &handle),
-                TaskMode::pause => println!("\"{}\" is paused", task.name),
-                TaskMode::stop => {}
+        println!("\"{}\" starting in {}", task.name, start_delay.as_secs());
+    }
+    let start = task_db.clock.now() + chrono::Duration::from_std(start_delay).unwrap();
+    let timer = Rc::new(RefCell::new(TaskTimer::new(
+        Schedule::new(task.recurrence(), task.zone(), start),
+        task.catch_up,
+        task.max_runs,
+        task.deadline(start),
+    )));
+    let ticks = get_tick_stream(timer.clone(), task_db.clock.clone(), &handle);
+    Box::new(ticks.for_each(move |tick| {
+        let invocation = match tick {
+            Tick::Run { due, runs } => run_due(&task, &timer, &task_db, &handle, &due, runs),
+            Tick::Finished(reason) => {
+                task_db.finish_task(&task.name, &reason);
+                return Box::new(future::ok(()))
+                    as Box<dyn Future<Item = (), Error = std::io::Error>>;
+            }
+            // The tick stream does the waiting.
+            Tick::Wait(_) => return Box::new(future::ok(())),
+        };
+        match task.schedule_mode {
+            ScheduleMode::fixed_rate => {
+                handle.spawn(invocation);
+                Box::new(future::ok(()))
+            }
+            ScheduleMode::fixed_delay => {
+                // The next run is measured from the end of this one.
+                let (timer, clock) = (timer.clone(), task_db.clock.clone());
+                Box::new(invocation.then(move |_| {
+                    timer.borrow_mut().restart(&*clock);
+                    Ok(())
+                }))
             }
-            future::ok(())
-        })
+        }
     }))
 }
 
+fn load_tasks(
+    path: &str,
+    defaults: &TaskDefaults,
+    clock: &dyn Clock,
+) -> Result<Vec<PeriodicTask>, String> {
+    let mut file = File::open(path).map_err(|err| format!("couldn't open {} ({})", path, err))?;
+    let mut yaml = String::new();
+    file.read_to_string(&mut yaml)
+        .map_err(|err| format!("couldn't read {}: {}", path, err))?;
+    let mut tasks_descriptions =
+        serde_yaml::from_str::<Vec<PeriodicTask>>(&yaml).map_err(|e| e.to_string())?;
+    for task in tasks_descriptions.iter_mut() {
+        task.apply_defaults(defaults);
+    }
+    tasks_descriptions
+        .iter()
+        .try_for_each(|task| task.validate(clock))?;
+    tasks_descriptions
+        .iter_mut()
+        .try_for_each(PeriodicTask::resolve_credentials)?;
+    Ok(tasks_descriptions)
+}
+
 fn run_futures_from_file(
     path: &str,
     task_db: Rc<TaskStateDb>,
     mut core: Core,
-    start_delay: Duration,
+    defaults: &TaskDefaults,
 ) {
-    match File::open(path) {
-        Err(err) => println!("couldn't open {} ({})", path, err.description()),
-        Ok(mut file) => {
-            let mut yaml = String::new();
-            match file.read_to_string(&mut yaml) {
-                Err(err) => println!("couldn't read {}: {}", path, err.description()),
-                Ok(_) => match serde_yaml::from_str::<Vec<PeriodicTask>>(&yaml) {
-                    Ok(tasks_descriptions) => {
-                        let mut tasks: Vec<Box<dyn Future<Item = (), Error = std::io::Error>>> =
-                            Vec::new();
-                        tasks.push(get_monitor_future(task_db.clone(), core.handle()));
-                        tasks.push(get_signal_future(
-                            task_db.clone(),
-                            SIGUSR1,
-                            TaskMode::pause,
-                            core.handle(),
-                        ));
-                        tasks.push(get_signal_future(
-                            task_db.clone(),
-                            SIGUSR2,
-                            TaskMode::run,
-                            core.handle(),
-                        ));
-                        tasks.push(get_signal_future(
-                            task_db.clone(),
-                            SIGTERM,
-                            TaskMode::stop,
-                            core.handle(),
-                        ));
-                        for task in tasks_descriptions {
-                            tasks.push(get_task_future(
-                                task,
-                                task_db.clone(),
-                                core.handle(),
-                                start_delay,
-                            ));
-                        }
-                        drop(core.run(future::join_all(tasks)))
-                    }
-                    Err(e) => println!("{}", e.description()),
-                },
+    // Loading a file has no side effects, so that it can be previewed, and
+    // log files are only opened here.
+    let loaded = load_tasks(path, defaults, &*task_db.clock).and_then(|mut tasks_descriptions| {
+        tasks_descriptions
+            .iter()
+            .try_for_each(PeriodicTask::check_privileges)?;
+        tasks_descriptions
+            .iter_mut()
+            .try_for_each(PeriodicTask::open_log_file)?;
+        Ok(tasks_descriptions)
+    });
+    match loaded {
+        Err(e) => println!("{}", e),
+        Ok(tasks_descriptions) => {
+            let start_delays = tasks_descriptions
+                .iter()
+                .map(|task| task.start_delay(&*task_db.clock))
+                .collect::<Vec<Duration>>();
+            let mut tasks = vec![
+                get_monitor_future(task_db.clone(), core.handle()),
+                get_signal_future(task_db.clone(), SIGUSR1, TaskMode::pause, core.handle()),
+                get_signal_future(task_db.clone(), SIGUSR2, TaskMode::run, core.handle()),
+                get_signal_future(task_db.clone(), SIGTERM, TaskMode::stop, core.handle()),
+                get_signal_future(task_db.clone(), SIGINT, TaskMode::stop, core.handle()),
+                get_signal_future(task_db.clone(), SIGHUP, TaskMode::stop, core.handle()),
+            ];
+            for (task, start_delay) in tasks_descriptions.into_iter().zip(start_delays) {
+                tasks.push(get_task_future(
+                    task,
+                    task_db.clone(),
+                    core.handle(),
+                    start_delay,
+                ));
+            }
+            drop(core.run(future::join_all(tasks)))
+        }
+    }
+}
+
+fn format_preview_time(time: &DateTime<Zone>) -> String {
+    time.format("%Y-%m-%d %H:%M:%S %:z").to_string()
+}
+
+// Prints when each task in a file would run, starting from `from`, without
+// running anything. Start splay and jitter are left out.
+fn preview_tasks_from_file(path: &str, from: DateTime<Utc>, count: usize, defaults: &TaskDefaults) {
+    let clock = ManualClock::new(from);
+    let tasks = match load_tasks(path, defaults, &clock) {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    for task in tasks {
+        let start = from + chrono::Duration::from_std(task.start_delay(&clock)).unwrap();
+        let schedule = Schedule::new(task.recurrence(), task.zone(), start);
+        let count = task
+            .max_runs
+            .map_or(count, |max_runs| count.min(max_runs as usize));
+        let entries = preview_runs(
+            &schedule,
+            &task.run_conditions(),
+            task.deadline(start),
+            count,
+        );
+        println!("\"{}\" ({}):", task.name, task.zone());
+        if entries.is_empty() {
+            println!("  no runs");
+        }
+        for entry in entries {
+            match entry {
+                PreviewEntry::Run(time) => println!("  {}", format_preview_time(&time)),
+                PreviewEntry::Skipped {
+                    first,
+                    count: 1,
+                    reason,
+                    ..
+                } => println!("  {} skipped, {}", format_preview_time(&first), reason),
+                PreviewEntry::Skipped {
+                    first,
+                    last,
+                    count,
+                    reason,
+                } => println!(
+                    "  {} to {}, {} runs skipped, {}",
+                    format_preview_time(&first),
+                    format_preview_time(&last),
+                    count,
+                    reason
+                ),
             }
         }
     }
 }
 
+fn format_history_run(run: &Run) -> String {
+    let start = run.start.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S");
+    match (&run.status, run.duration()) {
+        (Some(status), Some(duration)) => format!(
+            "PID {} started {}, {}{} after {:?}",
+            run.pid,
+            start,
+            if run.timed_out { "timed out, " } else { "" },
+            status,
+            // Milliseconds are plenty for a command's run time.
+            Duration::from_millis(duration.as_millis() as u64)
+        ),
+        _ => format!("PID {} started {}, still running", run.pid, start),
+    }
+}
+
+// Prints a task's recent runs and their output, as last written to a
+// running periodic's status file.
+fn show_history(status_path: &str, task_name: &str) {
+    let statuses = fs::read_to_string(status_path)
+        .map_err(|e| format!("couldn't read {} ({})", status_path, e))
+        .and_then(|yaml| {
+            serde_yaml::from_str::<BTreeMap<String, TaskStatus>>(&yaml)
+                .map_err(|e| format!("{}: {}", status_path, e))
+        });
+    let status = match statuses.map(|mut statuses| statuses.remove(task_name)) {
+        Ok(Some(status)) => status,
+        Ok(None) => {
+            println!("no task named \"{}\" in {}", task_name, status_path);
+            return;
+        }
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    println!(
+        "\"{}\" ({}, {} run(s), {} running):",
+        task_name,
+        if status.finished {
+            String::from("finished")
+        } else {
+            format!("{:?}", status.mode)
+        },
+        status.runs,
+        status.running
+    );
+    if status.history.is_empty() {
+        println!("  no runs");
+    }
+    for run in status.history {
+        println!("  {}", format_history_run(&run));
+        if run.output_truncated {
+            println!("    (earlier output dropped)");
+        }
+        for output in run.output {
+            println!("    {}: {}", output.stream, output.line);
+        }
+    }
+}
+
 fn run_future_from_args(
     matches: ArgMatches,
     task_db: Rc<TaskStateDb>,
     mut core: Core,
-    start_delay: Duration,
+    defaults: &TaskDefaults,
 ) {
     if let Some(cmd) = matches.values_of("COMMAND") {
         let task = PeriodicTask {
             name: String::from(matches.value_of("name").unwrap()),
-            interval_secs: matches
-                .value_of("interval")
-                .unwrap()
-                .parse::<u64>()
-                .unwrap(),
+            interval: parse_duration(matches.value_of("interval").unwrap()).unwrap(),
+            schedule: None,
+            align: false,
+            align_to: None,
+            schedule_mode: ScheduleMode::from_arg(matches.value_of("schedule-mode").unwrap()),
+            start_time: defaults.start_time,
+            timezone: Some(defaults.timezone),
+            start_splay: None,
+            jitter: None,
+            jitter_mode: JitterMode::random,
+            catch_up: default_catch_up(),
+            active_windows: Vec::new(),
+            blackout_windows: Vec::new(),
+            exclude_calendar: None,
+            max_runs: defaults.max_runs,
+            end_time: defaults.end_time,
+            run_for: None,
             max_concurrent: matches
                 .value_of("max-concurrent")
                 .unwrap()
                 .parse::<u32>()
                 .unwrap(),
-            cmd: cmd.map(|arg| arg.to_string()).collect(),
+            timeout: None,
+            timeout_signal: default_timeout_signal(),
+            kill_grace: default_kill_grace(),
+            stop_signal: default_stop_signal(),
+            process_group: default_process_group(),
+            cwd: matches.value_of("cwd").map(String::from),
+            env: matches.values_of("env").map_or(Vec::new(), |vars| {
+                vars.map(|var| parse_env_var(var).unwrap()).collect()
+            }),
+            env_file: matches
+                .value_of("env-file")
+                .map_or(Vec::new(), |path| load_env_file(path).unwrap()),
+            clear_env: matches.is_present("clear-env"),
+            user: None,
+            group: None,
+            supplementary_groups: None,
+            credentials: Credentials::default(),
+            limits: Limits::default(),
+            nice: None,
+            io_class: None,
+            io_priority: None,
+            cpu_affinity: None,
+            output: None,
+            log_file: None,
+            log_rotation: None,
+            log_writer: None,
+            history: matches
+                .value_of("history")
+                .map_or(DEFAULT_HISTORY, |history| history.parse::<usize>().unwrap()),
+            history_output: parse_size(matches.value_of("history-output").unwrap()).unwrap()
+                as usize,
+            shell: matches.is_present("shell"),
+            shell_path: None,
+            cmd: if matches.is_present("shell") {
+                TaskCommand::Line(cmd.collect::<Vec<&str>>().join(" "))
+            } else {
+                TaskCommand::Args(cmd.map(|arg| arg.to_string()).collect())
+            },
         };
+        if let Err(e) = task
+            .validate(&*task_db.clock)
+            .and_then(|_| task.check_privileges())
+        {
+            println!("{}", e);
+            return;
+        }
+        let start_delay = task.start_delay(&*task_db.clock);
         let futures = vec![
             get_monitor_future(task_db.clone(), core.handle()),
             get_signal_future(task_db.clone(), SIGUSR1, TaskMode::pause, core.handle()),
             get_signal_future(task_db.clone(), SIGUSR2, TaskMode::run, core.handle()),
             get_signal_future(task_db.clone(), SIGTERM, TaskMode::stop, core.handle()),
+            get_signal_future(task_db.clone(), SIGINT, TaskMode::stop, core.handle()),
+            get_signal_future(task_db.clone(), SIGHUP, TaskMode::stop, core.handle()),
             get_task_future(task, task_db.clone(), core.handle(), start_delay),
         ];
 
@@ -423,11 +1942,38 @@ fn run_future_from_args(
     }
 }
 
+fn validate_interval(value: String) -> Result<(), String> {
+    match parse_duration(&value) {
+        Ok(interval) if interval == Duration::from_secs(0) => {
+            Err(String::from("interval must be greater than zero"))
+        }
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn validate_cwd(value: String) -> Result<(), String> {
+    if Path::new(&value).is_dir() {
+        Ok(())
+    } else {
+        Err(format!("{} is not a directory", value))
+    }
+}
+
+fn validate_count(value: String) -> Result<(), String> {
+    match value.parse::<u32>() {
+        Ok(0) => Err(String::from("count must be greater than zero")),
+        Ok(_) => Ok(()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 fn main() {
     let matches = App::new("periodic")
         .version(crate_version!())
         .author(crate_authors!())
         .about("run commands periodically")
+        .setting(AppSettings::ArgsNegateSubcommands)
         .arg(
             Arg::with_name("file")
                 .empty_values(false)
@@ -447,8 +1993,12 @@ fn main() {
                 .empty_values(false)
                 .short("i")
                 .long("interval")
-                .default_value(DEFAULT_INTERVAL_SECS)
-                .help("interval, in seconds, between command invocations"),
+                .default_value(DEFAULT_INTERVAL)
+                .validator(validate_interval)
+                .help(concat!(
+                    "interval between command invocations, either in seconds or ",
+                    "as a duration such as \"500ms\", \"90s\", \"1h30m\" or \"2d\""
+                )),
         )
         .arg(
             Arg::with_name("max-concurrent")
@@ -466,40 +2016,265 @@ fn main() {
                 .default_value(DEFAULT_NAME)
                 .help("descriptive name for command"),
         )
+        .arg(
+            Arg::with_name("schedule-mode")
+                .empty_values(false)
+                .long("schedule-mode")
+                .possible_values(&["fixed_rate", "fixed_delay"])
+                .default_value(DEFAULT_SCHEDULE_MODE)
+                .help(concat!(
+                    "\"fixed_rate\" starts the command every interval, ",
+                    "\"fixed_delay\" waits for the interval after each invocation finishes"
+                )),
+        )
         .arg(
             Arg::with_name("start-time")
                 .short("s")
                 .long("start-time")
                 .takes_value(true)
+                .validator(|value| StartSpec::parse(&value).map(|_| ()))
+                .global(true)
+                .help(concat!(
+                    "start time for tasks: \"HH:MM\" for the next time the clock reads that, ",
+                    "\"hour(+MM)\", \"minute(+SS)\" or \"day(+HH:MM)\" to start at the next ",
+                    "hour, minute or day with an optional offset, a day of the week with an ",
+                    "optional time such as \"monday 09:00\", \"month(+N)\" for the first of next ",
+                    "month or N months ahead, \"in\" followed by a duration such as \"in 10m\", ",
+                    "or an ISO-8601 date and time. Defaults to now. ",
+                    "Tasks in a file may override this with their own start_time."
+                )),
+        )
+        .arg(
+            Arg::with_name("timezone")
+                .empty_values(false)
+                .long("timezone")
+                .default_value("local")
+                .validator(|value| Zone::parse(&value).map(|_| ()))
+                .global(true)
+                .help(concat!(
+                    "time zone for start times, schedules and time windows, either ",
+                    "\"local\" or an IANA name such as \"America/New_York\". ",
+                    "Tasks in a file may override this with their own timezone."
+                )),
+        )
+        .arg(
+            Arg::with_name("count")
+                .long("count")
+                .takes_value(true)
+                .validator(validate_count)
                 .help(concat!(
-                    "start time for tasks, either \"HH:MM\" for an absolute time ",
-                    "or \"hour(+MM)\" or \"minute(+SS)\" to start at the next hour ",
-                    "or minute, with an optional extra delay. Defaults to now."
+                    "stop after running the command this many times. ",
+                    "Tasks in a file may override this with their own max_runs."
                 )),
         )
+        .arg(
+            Arg::with_name("until")
+                .long("until")
+                .takes_value(true)
+                .validator(|value| EndTime::parse(&value).map(|_| ()))
+                .global(true)
+                .help(concat!(
+                    "stop at this time, either \"HH:MM\" for the next time the clock reads that ",
+                    "or \"YYYY-MM-DD HH:MM\". ",
+                    "Tasks in a file may override this with their own end_time."
+                )),
+        )
+        .arg(Arg::with_name("shell").long("shell").help(concat!(
+            "run the command with /bin/sh -c, so it can use pipes, redirects and ",
+            "other shell syntax. Quote the command to keep the shell you run periodic ",
+            "from interpreting them first."
+        )))
+        .arg(
+            Arg::with_name("cwd")
+                .long("cwd")
+                .takes_value(true)
+                .validator(validate_cwd)
+                .help("directory to run the command in"),
+        )
+        .arg(
+            Arg::with_name("env")
+                .short("e")
+                .long("env")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(|value| parse_env_var(&value).map(|_| ()))
+                .help("set an environment variable for the command, as NAME=value"),
+        )
+        .arg(
+            Arg::with_name("env-file")
+                .long("env-file")
+                .takes_value(true)
+                .validator(|value| load_env_file(&value).map(|_| ()))
+                .help(concat!(
+                    "file of NAME=value lines to set in the command's environment, ",
+                    "which --env takes precedence over"
+                )),
+        )
+        .arg(Arg::with_name("clear-env").long("clear-env").help(concat!(
+            "start the command with only the variables given by --env and --env-file, ",
+            "rather than periodic's environment"
+        )))
+        .arg(
+            Arg::with_name("history")
+                .long("history")
+                .takes_value(true)
+                .validator(|value| {
+                    value
+                        .parse::<usize>()
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help(concat!(
+                    "number of recent runs to keep, with their output, for the status file ",
+                    "and the history subcommand. Defaults to 10."
+                )),
+        )
+        .arg(
+            Arg::with_name("history-output")
+                .long("history-output")
+                .default_value(DEFAULT_HISTORY_OUTPUT)
+                .validator(|value| parse_size(&value).map(|_| ()))
+                .help(concat!(
+                    "how much of each run's output to keep in its history, as a size such ",
+                    "as \"64K\". Only tasks with prefix or file output have it kept; runs of ",
+                    "tasks with the default, inherit, are kept without their output."
+                )),
+        )
+        .arg(
+            Arg::with_name("propagate-signals")
+                .long("propagate-signals")
+                .help(concat!(
+                    "when told to stop, send each running command its task's stop_signal ",
+                    "(SIGTERM by default) instead of just waiting for it to finish"
+                )),
+        )
+        .arg(
+            Arg::with_name("shutdown-timeout")
+                .long("shutdown-timeout")
+                .takes_value(true)
+                .validator(|value| {
+                    parse_duration(&value)
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                })
+                .help(concat!(
+                    "when told to stop, send SIGKILL to any commands still running ",
+                    "after this long, as a duration such as \"30s\""
+                )),
+        )
+        .arg(
+            Arg::with_name("status-file")
+                .long("status-file")
+                .default_value(DEFAULT_STATUS_FILE)
+                .help(concat!(
+                    "file to keep up to date with each task's mode and recent runs, ",
+                    "which the history subcommand reads. An empty value turns it off."
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("show a task's recent runs and their output, from a status file")
+                .arg(
+                    Arg::with_name("status-file")
+                        .empty_values(false)
+                        .long("status-file")
+                        .default_value(DEFAULT_STATUS_FILE)
+                        .help("status file written by the running periodic"),
+                )
+                .arg(
+                    Arg::with_name("TASK")
+                        .required(true)
+                        .help("name of the task"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("next")
+                .about("list when the tasks in a file would run, without running them")
+                .arg(
+                    Arg::with_name("file")
+                        .empty_values(false)
+                        .short("f")
+                        .long("file")
+                        .required(true)
+                        .help("YAML file containing task descriptions"),
+                )
+                .arg(
+                    Arg::with_name("count")
+                        .long("count")
+                        .default_value(DEFAULT_PREVIEW_COUNT)
+                        .validator(validate_count)
+                        .help("number of runs to list for each task"),
+                )
+                .arg(
+                    Arg::with_name("from")
+                        .long("from")
+                        .takes_value(true)
+                        .validator(|value| parse_date_time_in(&value, Zone::Local).map(|_| ()))
+                        .help(concat!(
+                            "list runs from this ISO-8601 date and time instead of now, ",
+                            "in the --timezone time zone unless it has a UTC offset"
+                        )),
+                ),
+        )
         .get_matches();
 
-    let start_delay = match matches.value_of("start-time") {
-        Some(value) => periodic::time::get_start_delay_from_next(Local::now(), value)
-            .or_else(|_| periodic::time::get_start_delay_from_hh_mm(value)),
-        None => Ok(Duration::from_secs(0)),
+    let defaults = TaskDefaults {
+        start_time: matches
+            .value_of("start-time")
+            .map(|start_time| StartSpec::parse(start_time).unwrap()),
+        timezone: Zone::parse(matches.value_of("timezone").unwrap()).unwrap(),
+        max_runs: matches
+            .value_of("count")
+            .map(|count| count.parse::<u32>().unwrap()),
+        end_time: matches
+            .value_of("until")
+            .map(|until| EndTime::parse(until).unwrap()),
     };
+    let clock: Rc<dyn Clock> = Rc::new(SystemClock);
 
-    match start_delay {
-        Ok(start_delay) => {
-            let task_db = Rc::new(TaskStateDb::new());
-            let core = Core::new().unwrap();
-            if matches.is_present("file") {
-                run_futures_from_file(
-                    matches.value_of("file").unwrap(),
-                    task_db.clone(),
-                    core,
-                    start_delay,
-                )
-            } else {
-                run_future_from_args(matches, task_db.clone(), core, start_delay)
-            }
-        }
-        Err(e) => println!("{}", e),
+    if let Some(next) = matches.subcommand_matches("next") {
+        let from = match next.value_of("from") {
+            Some(from) => parse_date_time_in(from, defaults.timezone).unwrap(),
+            None => clock.now(),
+        };
+        preview_tasks_from_file(
+            next.value_of("file").unwrap(),
+            from,
+            next.value_of("count").unwrap().parse::<usize>().unwrap(),
+            &defaults,
+        );
+        return;
+    }
+
+    if let Some(history) = matches.subcommand_matches("history") {
+        show_history(
+            history.value_of("status-file").unwrap(),
+            history.value_of("TASK").unwrap(),
+        );
+        return;
+    }
+
+    let task_db = Rc::new(TaskStateDb::new(
+        clock,
+        matches.is_present("propagate-signals"),
+        matches
+            .value_of("shutdown-timeout")
+            .map(|timeout| parse_duration(timeout).unwrap()),
+        matches
+            .value_of("status-file")
+            .filter(|path| !path.is_empty())
+            .map(String::from),
+    ));
+    let core = Core::new().unwrap();
+    if matches.is_present("file") {
+        run_futures_from_file(
+            matches.value_of("file").unwrap(),
+            task_db.clone(),
+            core,
+            &defaults,
+        )
+    } else {
+        run_future_from_args(matches, task_db.clone(), core, &defaults)
     }
 }
@@ -0,0 +1,212 @@
+use std::io;
+use std::mem;
+
+use libc;
+
+const MIN_NICE: i32 = -20;
+const MAX_NICE: i32 = 19;
+const MAX_IO_PRIORITY: u8 = 7;
+// The level used by the kernel for a process that hasn't set its own.
+const DEFAULT_IO_PRIORITY: u8 = 4;
+
+// From linux/ioprio.h.
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: u32 = 13;
+
+/// An I/O scheduling class, as set by ionice.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IoClass {
+    Realtime,
+    BestEffort,
+    Idle,
+}
+
+impl IoClass {
+    pub fn parse(value: &str) -> Result<IoClass, String> {
+        match value {
+            "realtime" => Ok(IoClass::Realtime),
+            "best-effort" => Ok(IoClass::BestEffort),
+            "idle" => Ok(IoClass::Idle),
+            _ => Err(format!(
+                "invalid io_class \"{}\", expected realtime, best-effort or idle",
+                value
+            )),
+        }
+    }
+
+    fn number(self) -> libc::c_int {
+        match self {
+            IoClass::Realtime => 1,
+            IoClass::BestEffort => 2,
+            IoClass::Idle => 3,
+        }
+    }
+}
+
+/// A set of CPUs to run on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CpuSet {
+    cpus: Vec<usize>,
+}
+
+impl CpuSet {
+    /// Parses a list of CPU numbers and ranges, such as "0-3,6".
+    pub fn parse(value: &str) -> Result<CpuSet, String> {
+        let invalid = || format!("invalid CPU list \"{}\"", value);
+        let mut cpus = Vec::new();
+        for part in value.split(',').map(str::trim) {
+            let mut range = part.splitn(2, '-');
+            let first = range
+                .next()
+                .and_then(|first| first.trim().parse::<usize>().ok())
+                .ok_or_else(invalid)?;
+            let last = match range.next() {
+                Some(last) => last.trim().parse::<usize>().map_err(|_| invalid())?,
+                None => first,
+            };
+            if last < first {
+                return Err(invalid());
+            }
+            cpus.extend(first..=last);
+        }
+        CpuSet::from_cpus(cpus)
+    }
+
+    pub fn from_cpus(mut cpus: Vec<usize>) -> Result<CpuSet, String> {
+        cpus.sort_unstable();
+        cpus.dedup();
+        match cpus.last() {
+            None => Err(String::from("no CPUs given")),
+            Some(&cpu) if cpu >= libc::CPU_SETSIZE as usize => {
+                Err(format!("CPU {} is out of range", cpu))
+            }
+            Some(_) => Ok(CpuSet { cpus }),
+        }
+    }
+
+    pub fn cpus(&self) -> &[usize] {
+        &self.cpus
+    }
+
+    // Periodic's own CPUs, which are the ones its children can use.
+    fn available() -> io::Result<libc::cpu_set_t> {
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        if unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) } == 0 {
+            Ok(set)
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+/// How a task's commands are prioritized for CPU time and I/O, and which
+/// CPUs they may run on.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Priority {
+    pub nice: Option<i32>,
+    pub io_class: Option<IoClass>,
+    pub io_priority: Option<u8>,
+    pub cpu_affinity: Option<CpuSet>,
+}
+
+impl Priority {
+    pub fn is_empty(&self) -> bool {
+        *self == Priority::default()
+    }
+
+    // The I/O class and level to set, if any. A level on its own is for the
+    // best-effort class.
+    fn io(&self) -> Option<(IoClass, u8)> {
+        match (self.io_class, self.io_priority) {
+            (None, None) => None,
+            (class, level) => Some((
+                class.unwrap_or(IoClass::BestEffort),
+                level.unwrap_or(DEFAULT_IO_PRIORITY),
+            )),
+        }
+    }
+
+    /// Checks that the settings are in range and can be used together.
+    pub fn validate(&self) -> Result<(), String> {
+        if let Some(nice) = self.nice {
+            if !(MIN_NICE..=MAX_NICE).contains(&nice) {
+                return Err(format!(
+                    "nice must be between {} and {}",
+                    MIN_NICE, MAX_NICE
+                ));
+            }
+        }
+        if let Some(level) = self.io_priority {
+            if level > MAX_IO_PRIORITY {
+                return Err(format!(
+                    "io_priority must be between 0 and {}",
+                    MAX_IO_PRIORITY
+                ));
+            }
+            if self.io_class == Some(IoClass::Idle) {
+                return Err(String::from(
+                    "io_priority can't be used with the idle io_class",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that periodic is allowed to make the settings: only root can
+    /// lower niceness below periodic's own or use the realtime I/O class,
+    /// and only periodic's own CPUs can be used.
+    pub fn check(&self) -> Result<(), String> {
+        let root = unsafe { libc::geteuid() } == 0;
+        if let Some(nice) = self.nice {
+            let current = unsafe { libc::getpriority(libc::PRIO_PROCESS, 0) };
+            if nice < current && !root {
+                return Err(format!("setting nice below {} needs root", current));
+            }
+        }
+        if self.io_class == Some(IoClass::Realtime) && !root {
+            return Err(String::from("the realtime io_class needs root"));
+        }
+        if let Some(ref cpu_affinity) = self.cpu_affinity {
+            let available = CpuSet::available()
+                .map_err(|e| format!("couldn't read the available CPUs: {}", e))?;
+            if let Some(cpu) = cpu_affinity
+                .cpus
+                .iter()
+                .find(|&&cpu| !unsafe { libc::CPU_ISSET(cpu, &available) })
+            {
+                return Err(format!("CPU {} isn't available", cpu));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the nice value, I/O priority and CPU affinity of the current
+    /// process, leaving any that aren't given as they are.
+    pub fn apply(&self) -> io::Result<()> {
+        let check = |status: libc::c_long| {
+            if status == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        };
+        if let Some(nice) = self.nice {
+            check(unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) }.into())?;
+        }
+        if let Some((class, level)) = self.io() {
+            let ioprio = class.number() << IOPRIO_CLASS_SHIFT | libc::c_int::from(level);
+            check(unsafe { libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ioprio) })?;
+        }
+        if let Some(ref cpu_affinity) = self.cpu_affinity {
+            let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+            for &cpu in &cpu_affinity.cpus {
+                unsafe { libc::CPU_SET(cpu, &mut set) };
+            }
+            check(
+                unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) }
+                    .into(),
+            )?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use libc;
+
+const SIGNALS: [(&str, i32); 22] = [
+    ("SIGHUP", libc::SIGHUP),
+    ("SIGINT", libc::SIGINT),
+    ("SIGQUIT", libc::SIGQUIT),
+    ("SIGILL", libc::SIGILL),
+    ("SIGTRAP", libc::SIGTRAP),
+    ("SIGABRT", libc::SIGABRT),
+    ("SIGBUS", libc::SIGBUS),
+    ("SIGFPE", libc::SIGFPE),
+    ("SIGKILL", libc::SIGKILL),
+    ("SIGUSR1", libc::SIGUSR1),
+    ("SIGSEGV", libc::SIGSEGV),
+    ("SIGUSR2", libc::SIGUSR2),
+    ("SIGPIPE", libc::SIGPIPE),
+    ("SIGALRM", libc::SIGALRM),
+    ("SIGTERM", libc::SIGTERM),
+    ("SIGCHLD", libc::SIGCHLD),
+    ("SIGCONT", libc::SIGCONT),
+    ("SIGSTOP", libc::SIGSTOP),
+    ("SIGTSTP", libc::SIGTSTP),
+    ("SIGXCPU", libc::SIGXCPU),
+    ("SIGXFSZ", libc::SIGXFSZ),
+    ("SIGWINCH", libc::SIGWINCH),
+];
+
+/// Reads a signal given by name, with or without the "SIG" prefix and in
+/// any case, or by number.
+pub fn parse_signal(value: &str) -> Result<i32, String> {
+    if let Ok(signum) = value.parse::<i32>() {
+        return match signal_name(signum) {
+            Some(_) => Ok(signum),
+            None => Err(format!("unknown signal number {}", signum)),
+        };
+    }
+    let name = value.to_ascii_uppercase();
+    SIGNALS
+        .iter()
+        .find(|&&(signal, _)| name == signal || name == signal[3..])
+        .map(|&(_, signum)| signum)
+        .ok_or_else(|| format!("unknown signal \"{}\"", value))
+}
+
+/// The name of a signal, such as "SIGTERM", if it's one of the common ones.
+pub fn signal_name(signum: i32) -> Option<&'static str> {
+    SIGNALS
+        .iter()
+        .find(|&&(_, number)| number == signum)
+        .map(|&(name, _)| name)
+}
@@ -5,59 +5,1996 @@ use std::time::Duration;
 use chrono::prelude::*;
 
 #[cfg(test)]
-use time::get_start_delay_from_next;
+use chrono_tz::America::New_York;
+#[cfg(test)]
+use chrono_tz::Tz;
+
+#[cfg(test)]
+use time::{get_start_delay, get_start_delay_from_hh_mm, get_start_delay_from_next};
+
+#[cfg(test)]
+fn local_time(time: &str) -> DateTime<Local> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S")
+        .unwrap()
+        .and_local_timezone(Local)
+        .unwrap()
+}
+
+// New York's clocks went forward from 02:00 to 03:00 on 2019-03-10, and
+// back from 02:00 to 01:00 on 2019-11-03.
+#[cfg(test)]
+fn new_york_time(time: &str) -> DateTime<Tz> {
+    New_York
+        .from_local_datetime(&NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S").unwrap())
+        .earliest()
+        .unwrap()
+}
+
+#[cfg(test)]
+fn utc_time(time: &str) -> DateTime<Utc> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S")
+        .unwrap()
+        .and_utc()
+}
 
 #[test]
 fn test_start_delay_next_hour_16_45_hour15() {
-    let cur_time = Local
-        .datetime_from_str("2019-09-02T16:45:00", "%Y-%m-%dT%H:%M:%S")
-        .unwrap();
+    let cur_time = local_time("2019-09-02T16:45:00");
     match get_start_delay_from_next(cur_time, "hour+15") {
         Ok(delay) => assert_eq!(delay, Duration::from_secs(1800)),
-        Err(e) => panic!(e),
+        Err(e) => panic!("{}", e),
     }
 }
 
 #[test]
 fn test_start_delay_next_hour_22_45_hour15() {
-    let cur_time = Local
-        .datetime_from_str("2019-09-02T22:45:00", "%Y-%m-%dT%H:%M:%S")
-        .unwrap();
+    let cur_time = local_time("2019-09-02T22:45:00");
     match get_start_delay_from_next(cur_time, "hour+15") {
         Ok(delay) => assert_eq!(delay, Duration::from_secs(1800)),
-        Err(e) => panic!(e),
+        Err(e) => panic!("{}", e),
     }
 }
 
 #[test]
 fn test_start_delay_next_hour_23_01_hour() {
-    let cur_time = Local
-        .datetime_from_str("2019-09-02T23:01:00", "%Y-%m-%dT%H:%M:%S")
-        .unwrap();
+    let cur_time = local_time("2019-09-02T23:01:00");
     match get_start_delay_from_next(cur_time, "hour") {
         Ok(delay) => assert_eq!(delay, Duration::from_secs(3540)),
-        Err(e) => panic!(e),
+        Err(e) => panic!("{}", e),
     }
 }
 
 #[test]
 fn test_start_delay_next_hour_23_45_hour20() {
-    let cur_time = Local
-        .datetime_from_str("2019-09-02T23:45:00", "%Y-%m-%dT%H:%M:%S")
-        .unwrap();
+    let cur_time = local_time("2019-09-02T23:45:00");
     match get_start_delay_from_next(cur_time, "hour+20") {
         Ok(delay) => assert_eq!(delay, Duration::from_secs(2100)),
-        Err(e) => panic!(e),
+        Err(e) => panic!("{}", e),
     }
 }
 
 #[test]
 fn test_start_delay_next_hour_23_45_hour() {
-    let cur_time = Local
-        .datetime_from_str("2019-09-02T23:45:00", "%Y-%m-%dT%H:%M:%S")
-        .unwrap();
+    let cur_time = local_time("2019-09-02T23:45:00");
     match get_start_delay_from_next(cur_time, "hour") {
         Ok(delay) => assert_eq!(delay, Duration::from_secs(900)),
-        Err(e) => panic!(e),
+        Err(e) => panic!("{}", e),
+    }
+}
+
+#[test]
+fn test_start_delay_either_format() {
+    let cur_time = local_time("2019-09-02T16:45:00");
+    assert_eq!(
+        get_start_delay(cur_time, "minute+30"),
+        Ok(Duration::from_secs(30))
+    );
+    assert!(get_start_delay(cur_time, "03:00").is_ok());
+    assert!(get_start_delay(cur_time, "later").is_err());
+}
+
+#[test]
+fn test_start_delay_hh_mm() {
+    let cur_time = local_time("2019-09-02T16:45:00");
+    assert_eq!(
+        get_start_delay_from_hh_mm(cur_time, "17:00"),
+        Ok(Duration::from_secs(900))
+    );
+    assert_eq!(
+        get_start_delay_from_hh_mm(cur_time, "16:30"),
+        Ok(Duration::from_secs(85500))
+    );
+}
+
+#[test]
+fn test_start_delay_hh_mm_spring_forward() {
+    // 23:00 to 02:30 is three and a half hours on the clock, but 02:30 is
+    // skipped and becomes 03:30, which is still three and a half hours away.
+    let cur_time = new_york_time("2019-03-09T23:00:00");
+    assert_eq!(
+        get_start_delay_from_hh_mm(cur_time, "02:30"),
+        Ok(Duration::from_secs(3 * 3600 + 1800))
+    );
+    // Only two hours pass between midnight and 03:00 on the clock.
+    let cur_time = new_york_time("2019-03-10T00:00:00");
+    assert_eq!(
+        get_start_delay_from_hh_mm(cur_time, "03:00"),
+        Ok(Duration::from_secs(2 * 3600))
+    );
+}
+
+#[test]
+fn test_start_delay_hh_mm_fall_back() {
+    // Four hours pass between midnight and 03:00 on the clock.
+    let cur_time = new_york_time("2019-11-03T00:00:00");
+    assert_eq!(
+        get_start_delay_from_hh_mm(cur_time, "03:00"),
+        Ok(Duration::from_secs(4 * 3600))
+    );
+    // 01:30 happens twice; the start is at the first one.
+    assert_eq!(
+        get_start_delay_from_hh_mm(cur_time, "01:30"),
+        Ok(Duration::from_secs(3600 + 1800))
+    );
+}
+
+#[test]
+fn test_start_delay_next_hour_fall_back() {
+    // At 01:45 the first time round, the repeated 01:15 has already passed,
+    // so the next "15 minutes past the hour" is at 02:15 on the clock.
+    let cur_time = new_york_time("2019-11-03T01:45:00");
+    assert_eq!(
+        get_start_delay_from_next(cur_time, "hour+15"),
+        Ok(Duration::from_secs(3600 + 1800))
+    );
+}
+
+#[cfg(test)]
+use time::StartSpec;
+
+#[cfg(test)]
+fn start_after(now: &str, start_at: &str) -> DateTime<Tz> {
+    StartSpec::parse(start_at)
+        .unwrap()
+        .start_after(&new_york_time(now))
+}
+
+#[test]
+fn test_start_spec_parse() {
+    let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+    assert_eq!(
+        StartSpec::parse("09:30"),
+        Ok(StartSpec::TimeOfDay(time(9, 30)))
+    );
+    assert_eq!(
+        StartSpec::parse("day+03:30"),
+        Ok(StartSpec::TimeOfDay(time(3, 30)))
+    );
+    assert_eq!(
+        StartSpec::parse("day"),
+        Ok(StartSpec::TimeOfDay(time(0, 0)))
+    );
+    assert_eq!(StartSpec::parse("hour+15"), Ok(StartSpec::NextHour(15)));
+    assert_eq!(StartSpec::parse("minute"), Ok(StartSpec::NextMinute(0)));
+    assert_eq!(StartSpec::parse("month+1"), Ok(StartSpec::NextMonth(1)));
+    assert_eq!(
+        StartSpec::parse("in 1h 30m"),
+        Ok(StartSpec::After(Duration::from_secs(5400)))
+    );
+    assert_eq!(
+        StartSpec::parse("Monday 09:00"),
+        Ok(StartSpec::Weekday(Weekday::Mon, time(9, 0)))
+    );
+    assert_eq!(
+        StartSpec::parse("fri"),
+        Ok(StartSpec::Weekday(Weekday::Fri, time(0, 0)))
+    );
+    assert_eq!(
+        StartSpec::parse("2019-09-02T10:00"),
+        Ok(StartSpec::At(
+            NaiveDate::from_ymd_opt(2019, 9, 2)
+                .unwrap()
+                .and_time(time(10, 0))
+        ))
+    );
+    assert_eq!(
+        StartSpec::parse("2019-09-02 10:00:00"),
+        StartSpec::parse("2019-09-02T10:00")
+    );
+    match StartSpec::parse("2019-09-02T10:00:00+02:00") {
+        Ok(StartSpec::AtInstant(instant)) => {
+            assert_eq!(instant, utc_time("2019-09-02T08:00:00"))
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_start_spec_errors() {
+    let error = |start_at| StartSpec::parse(start_at).unwrap_err();
+    assert_eq!(
+        error("12:345"),
+        "invalid start time \"12:345\": \"12:345\" is not a time of day in HH:MM form"
+    );
+    assert_eq!(
+        error("24:00"),
+        "invalid start time \"24:00\": \"24:00\" is not a time of day in HH:MM form"
+    );
+    assert_eq!(
+        error("hour+75"),
+        "invalid start time \"hour+75\": \"75\" in \"hour+75\" is not a number of minutes from 0 to 59"
+    );
+    assert_eq!(
+        error("fooday 09:00"),
+        "invalid start time \"fooday 09:00\": unknown word \"fooday\""
+    );
+    assert_eq!(
+        error("monday 09:00 sharp"),
+        "invalid start time \"monday 09:00 sharp\": unexpected \"sharp\""
+    );
+    assert_eq!(
+        error("in 10 minutes"),
+        concat!(
+            "invalid start time \"in 10 minutes\": ",
+            "invalid duration \"10minutes\": unknown unit (use ms, s, m, h or d)"
+        )
+    );
+    assert_eq!(
+        error("in 200000000000d"),
+        "invalid start time \"in 200000000000d\": \"200000000000d\" is too far in the future"
+    );
+    assert_eq!(
+        error("2019-13-02T10:00"),
+        "invalid start time \"2019-13-02T10:00\": \"2019-13-02T10:00\" is not an ISO-8601 date and time"
+    );
+    assert_eq!(error(""), "invalid start time \"\": it is empty");
+}
+
+#[test]
+fn test_start_spec_anchors() {
+    // 2019-09-04 is a Wednesday.
+    assert_eq!(
+        start_after("2019-09-04T16:45:00", "day+03:30"),
+        new_york_time("2019-09-05T03:30:00")
+    );
+    assert_eq!(
+        start_after("2019-09-04T16:45:00", "monday 09:00"),
+        new_york_time("2019-09-09T09:00:00")
+    );
+    assert_eq!(
+        start_after("2019-09-04T08:00:00", "wed 09:00"),
+        new_york_time("2019-09-04T09:00:00")
+    );
+    assert_eq!(
+        start_after("2019-09-04T10:00:00", "wed 09:00"),
+        new_york_time("2019-09-11T09:00:00")
+    );
+    assert_eq!(
+        start_after("2019-09-04T16:45:00", "month+1"),
+        new_york_time("2019-10-01T00:00:00")
+    );
+    assert_eq!(
+        start_after("2019-12-04T16:45:00", "month+2"),
+        new_york_time("2020-02-01T00:00:00")
+    );
+    assert_eq!(
+        start_after("2019-09-04T16:45:00", "in 10m"),
+        new_york_time("2019-09-04T16:55:00")
+    );
+    // A start past the end of the calendar is as late as it can be.
+    assert_eq!(
+        start_after("2019-09-04T16:45:00", "in 600000000d"),
+        resolve_local(&New_York, &NaiveDate::MAX.and_time(NaiveTime::MIN))
+    );
+}
+
+#[test]
+fn test_start_spec_absolute() {
+    assert_eq!(
+        start_after("2019-09-04T16:45:00", "2019-09-06T08:00"),
+        new_york_time("2019-09-06T08:00:00")
+    );
+    assert_eq!(
+        start_after("2019-09-04T16:45:00", "2019-09-06T08:00:00Z"),
+        new_york_time("2019-09-06T04:00:00")
+    );
+    // A time that's already passed means starting straight away.
+    assert_eq!(
+        start_after("2019-09-04T16:45:00", "2019-09-01T08:00"),
+        new_york_time("2019-09-04T16:45:00")
+    );
+}
+
+#[test]
+fn test_resolve_local_skipped_and_repeated() {
+    let skipped =
+        NaiveDateTime::parse_from_str("2019-03-10T02:30:00", "%Y-%m-%dT%H:%M:%S").unwrap();
+    assert_eq!(
+        resolve_local(&New_York, &skipped),
+        utc_time("2019-03-10T07:30:00")
+    );
+    let repeated =
+        NaiveDateTime::parse_from_str("2019-11-03T01:30:00", "%Y-%m-%dT%H:%M:%S").unwrap();
+    assert_eq!(
+        resolve_local(&New_York, &repeated),
+        utc_time("2019-11-03T05:30:00")
+    );
+}
+
+#[test]
+fn test_zone_parse() {
+    assert_eq!(Zone::parse("local"), Ok(Zone::Local));
+    assert_eq!(Zone::parse("America/New_York"), Ok(Zone::Named(New_York)));
+    assert!(Zone::parse("Mars/Olympus_Mons").is_err());
+    let now = utc_time("2019-11-03T05:30:00").with_timezone(&Zone::Named(New_York));
+    assert_eq!(now.hour(), 1);
+    assert_eq!(now.offset().fix().local_minus_utc(), -4 * 3600);
+}
+
+#[cfg(test)]
+use time::{resolve_local, Zone};
+
+#[cfg(test)]
+use time::EndTime;
+
+#[cfg(test)]
+fn end_time(start: &str, end_at: &str) -> DateTime<Tz> {
+    EndTime::parse(end_at).unwrap().after(&new_york_time(start))
+}
+
+#[test]
+fn test_end_time_later_today() {
+    assert_eq!(
+        end_time("2019-09-02T09:00:00", "18:00"),
+        new_york_time("2019-09-02T18:00:00")
+    );
+}
+
+#[test]
+fn test_end_time_tomorrow() {
+    assert_eq!(
+        end_time("2019-09-02T20:00:00", "06:30"),
+        new_york_time("2019-09-03T06:30:00")
+    );
+    assert_eq!(
+        end_time("2019-09-02T20:00:00", "20:00"),
+        new_york_time("2019-09-03T20:00:00")
+    );
+}
+
+#[test]
+fn test_end_time_with_date() {
+    assert_eq!(
+        end_time("2019-09-02T20:00:00", "2019-09-05 12:00"),
+        new_york_time("2019-09-05T12:00:00")
+    );
+    assert_eq!(
+        end_time("2019-09-02T20:00:00", "2019-09-05T12:00:30"),
+        new_york_time("2019-09-05T12:00:30")
+    );
+}
+
+#[test]
+fn test_end_time_invalid() {
+    assert_eq!(
+        EndTime::parse("6pm"),
+        Err(String::from("invalid end time: 6pm"))
+    );
+    assert!(EndTime::parse("25:00").is_err());
+}
+
+#[cfg(test)]
+use time::CronSchedule;
+
+#[cfg(test)]
+fn cron_next(expr: &str, now: &str) -> DateTime<Local> {
+    CronSchedule::parse(expr)
+        .unwrap()
+        .next_after(local_time(now))
+        .unwrap()
+}
+
+#[test]
+fn test_cron_weekdays_at_02_15() {
+    // 2019-09-06 is a Friday, so the next weekday run is on Monday.
+    assert_eq!(
+        cron_next("15 2 * * MON-FRI", "2019-09-06T02:15:00"),
+        local_time("2019-09-09T02:15:00")
+    );
+    assert_eq!(
+        cron_next("15 2 * * mon-fri", "2019-09-09T02:14:59"),
+        local_time("2019-09-09T02:15:00")
+    );
+}
+
+#[test]
+fn test_cron_steps_and_lists() {
+    assert_eq!(
+        cron_next("*/15 * * * *", "2019-09-02T16:46:00"),
+        local_time("2019-09-02T17:00:00")
+    );
+    assert_eq!(
+        cron_next("5,35 9-17/4 * * *", "2019-09-02T13:35:00"),
+        local_time("2019-09-02T17:05:00")
+    );
+    assert_eq!(
+        cron_next("10/20 * * * *", "2019-09-02T16:31:00"),
+        local_time("2019-09-02T16:50:00")
+    );
+    // A step past the end of the range just leaves the first value.
+    assert_eq!(
+        cron_next("5/4294967295 * * * *", "2019-09-02T16:31:00"),
+        local_time("2019-09-02T17:05:00")
+    );
+}
+
+#[test]
+fn test_cron_seconds_field() {
+    assert_eq!(
+        cron_next("*/10 * * * * *", "2019-09-02T16:45:03"),
+        local_time("2019-09-02T16:45:10")
+    );
+}
+
+#[test]
+fn test_cron_month_and_day_names() {
+    assert_eq!(
+        cron_next("0 0 1 jan,jul *", "2019-09-02T16:45:00"),
+        local_time("2020-01-01T00:00:00")
+    );
+    // 7 is Sunday, as is 0.
+    assert_eq!(
+        cron_next("0 12 * * 7", "2019-09-02T16:45:00"),
+        local_time("2019-09-08T12:00:00")
+    );
+}
+
+#[test]
+fn test_cron_day_of_month_or_day_of_week() {
+    // Restricting both day fields matches either the 13th or a Friday.
+    assert_eq!(
+        cron_next("0 0 13 * FRI", "2019-09-02T16:45:00"),
+        local_time("2019-09-06T00:00:00")
+    );
+    assert_eq!(
+        cron_next("0 0 13 * *", "2019-09-02T16:45:00"),
+        local_time("2019-09-13T00:00:00")
+    );
+}
+
+#[test]
+fn test_cron_macros() {
+    assert_eq!(
+        cron_next("@daily", "2019-12-31T16:45:00"),
+        local_time("2020-01-01T00:00:00")
+    );
+    assert_eq!(
+        CronSchedule::parse("@hourly"),
+        CronSchedule::parse("0 * * * *")
+    );
+}
+
+#[test]
+fn test_cron_spring_forward() {
+    let schedule = CronSchedule::parse("30 2 * * *").unwrap();
+    let next = schedule
+        .next_after(new_york_time("2019-03-09T12:00:00"))
+        .unwrap();
+    assert_eq!(next, utc_time("2019-03-10T07:30:00"));
+    assert_eq!(
+        schedule.next_after(next).unwrap(),
+        new_york_time("2019-03-11T02:30:00")
+    );
+}
+
+#[test]
+fn test_cron_fall_back() {
+    // A daily 01:30 run happens once, not twice, on the day 01:30 repeats.
+    let schedule = CronSchedule::parse("30 1 * * *").unwrap();
+    let next = schedule
+        .next_after(new_york_time("2019-11-03T00:00:00"))
+        .unwrap();
+    assert_eq!(next, utc_time("2019-11-03T05:30:00"));
+    assert_eq!(
+        schedule.next_after(next).unwrap(),
+        new_york_time("2019-11-04T01:30:00")
+    );
+    // Nor does it run during the second 01:30.
+    let second_time_round = utc_time("2019-11-03T06:10:00").with_timezone(&New_York);
+    assert_eq!(
+        schedule.next_after(second_time_round).unwrap(),
+        new_york_time("2019-11-04T01:30:00")
+    );
+}
+
+#[test]
+fn test_cron_never_matches() {
+    let schedule = CronSchedule::parse("0 0 31 2 *").unwrap();
+    assert_eq!(schedule.next_after(local_time("2019-09-02T16:45:00")), None);
+}
+
+#[test]
+fn test_cron_invalid_expressions() {
+    for expr in &[
+        "* * * *",
+        "60 * * * *",
+        "* 24 * * *",
+        "* * 0 * *",
+        "* * * 13 *",
+        "* * * * FOO",
+        "*/0 * * * *",
+        "10-5 * * * *",
+        "* * * * * * *",
+    ] {
+        assert!(
+            CronSchedule::parse(expr).is_err(),
+            "{} should not parse",
+            expr
+        );
+    }
+}
+
+#[cfg(test)]
+use time::{parse_duration, DurationError};
+
+#[test]
+fn test_parse_duration() {
+    assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+    assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+    assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(5400)));
+    assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(172800)));
+    assert_eq!(parse_duration("1m500ms"), Ok(Duration::from_millis(60500)));
+}
+
+#[test]
+fn test_parse_duration_errors() {
+    assert_eq!(parse_duration(" "), Err(DurationError::Empty));
+    assert_eq!(
+        parse_duration("h"),
+        Err(DurationError::MissingNumber(String::from("h")))
+    );
+    assert_eq!(
+        parse_duration("10x"),
+        Err(DurationError::UnknownUnit(String::from("10x")))
+    );
+    assert_eq!(
+        parse_duration("1.5h"),
+        Err(DurationError::UnknownUnit(String::from("1.5h")))
+    );
+    assert_eq!(
+        parse_duration("99999999999999999999d"),
+        Err(DurationError::Overflow(String::from(
+            "99999999999999999999d"
+        )))
+    );
+}
+
+#[cfg(test)]
+use time::{hashed_offset, random_offset};
+
+#[test]
+fn test_random_offset_within_max() {
+    let max = Duration::from_millis(1500);
+    for _ in 0..100 {
+        assert!(random_offset(max) <= max);
+    }
+    assert_eq!(
+        random_offset(Duration::from_secs(0)),
+        Duration::from_secs(0)
+    );
+}
+
+#[test]
+fn test_hashed_offset_is_stable() {
+    let max = Duration::from_secs(60);
+    let offset = hashed_offset("backup@host-1", max);
+    assert!(offset <= max);
+    assert_eq!(offset, hashed_offset("backup@host-1", max));
+    assert_ne!(offset, hashed_offset("backup@host-2", max));
+    assert_eq!(
+        hashed_offset("backup@host-1", Duration::from_secs(0)),
+        Duration::from_secs(0)
+    );
+}
+
+#[cfg(test)]
+use time::{is_in_windows, TimeWindow};
+
+#[test]
+fn test_time_window_business_hours() {
+    let window = TimeWindow::parse("MON-FRI 09:00-17:00").unwrap();
+    // 2019-09-02 is a Monday.
+    assert!(window.contains(&local_time("2019-09-02T09:00:00")));
+    assert!(window.contains(&local_time("2019-09-02T16:59:59")));
+    assert!(!window.contains(&local_time("2019-09-02T17:00:00")));
+    assert!(!window.contains(&local_time("2019-09-02T08:59:59")));
+    assert!(!window.contains(&local_time("2019-09-07T12:00:00")));
+}
+
+#[test]
+fn test_time_window_past_midnight() {
+    let window = TimeWindow::parse("fri 22:00-02:00").unwrap();
+    // Friday night into Saturday morning, but not Thursday night.
+    assert!(window.contains(&local_time("2019-09-06T23:30:00")));
+    assert!(window.contains(&local_time("2019-09-07T01:30:00")));
+    assert!(!window.contains(&local_time("2019-09-06T01:30:00")));
+    assert!(!window.contains(&local_time("2019-09-07T22:30:00")));
+}
+
+#[test]
+fn test_time_window_end_of_day() {
+    let window = TimeWindow::parse("SAT,SUN 00:00-24:00").unwrap();
+    assert!(window.contains(&local_time("2019-09-08T23:59:59")));
+    assert!(!window.contains(&local_time("2019-09-09T00:00:00")));
+}
+
+#[test]
+fn test_time_window_invalid() {
+    for spec in &[
+        "",
+        "09:00",
+        "9:00-17:00",
+        "09:00-17:60",
+        "24:00-01:00",
+        "FUNDAY 09:00-17:00",
+        "MON FRI 09:00-17:00",
+    ] {
+        assert!(
+            TimeWindow::parse(spec).is_err(),
+            "{} should not parse",
+            spec
+        );
+    }
+}
+
+#[test]
+fn test_active_and_blackout_windows() {
+    let active = vec![TimeWindow::parse("08:00-18:00").unwrap()];
+    let blackout = vec![TimeWindow::parse("12:00-13:00").unwrap()];
+    assert!(is_in_windows(&local_time("2019-09-02T03:00:00"), &[], &[]));
+    assert!(is_in_windows(
+        &local_time("2019-09-02T09:00:00"),
+        &active,
+        &blackout
+    ));
+    assert!(!is_in_windows(
+        &local_time("2019-09-02T12:30:00"),
+        &active,
+        &blackout
+    ));
+    assert!(!is_in_windows(
+        &local_time("2019-09-02T19:00:00"),
+        &active,
+        &blackout
+    ));
+    assert!(!is_in_windows(
+        &local_time("2019-09-02T12:30:00"),
+        &[],
+        &blackout
+    ));
+}
+
+#[test]
+fn test_time_window_in_time_zone() {
+    let window = TimeWindow::parse("09:00-17:00").unwrap();
+    let time = utc_time("2019-09-02T14:00:00");
+    assert!(window.contains(&time.with_timezone(&New_York)));
+    assert!(!window.contains(&time.with_timezone(&chrono_tz::Asia::Tokyo)));
+}
+
+#[cfg(test)]
+use clock::{Clock, ManualClock};
+#[cfg(test)]
+use time::{CatchUp, DueRuns, Recurrence, Schedule, MAX_CATCH_UP_RUNS};
+
+// Steps the clock forward until `until`, collecting the runs the schedule
+// says are due after each step.
+#[cfg(test)]
+fn run_schedule(
+    schedule: &mut Schedule,
+    clock: &ManualClock,
+    step: Duration,
+    until: &str,
+) -> Vec<DateTime<Utc>> {
+    let mut runs = Vec::new();
+    while clock.now() <= utc_time(until) {
+        while let Some(due) = schedule.take_due(clock) {
+            runs.push(due.scheduled.with_timezone(&Utc));
+        }
+        clock.advance(step);
+    }
+    runs
+}
+
+#[test]
+fn test_manual_clock() {
+    let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+    clock.advance(Duration::from_millis(1500));
+    assert_eq!(
+        clock.now(),
+        utc_time("2019-09-02T10:00:01") + chrono::Duration::milliseconds(500)
+    );
+    clock.set(utc_time("2019-09-03T00:00:00"));
+    assert_eq!(Zone::Named(New_York).now(&clock).hour(), 20);
+}
+
+#[test]
+fn test_schedule_interval() {
+    let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+    let mut schedule = Schedule::new(
+        Recurrence::Interval(Duration::from_secs(300)),
+        Zone::Local,
+        clock.now(),
+    );
+    assert_eq!(schedule.delay(&clock), Some(Duration::from_secs(0)));
+    let runs = run_schedule(
+        &mut schedule,
+        &clock,
+        Duration::from_secs(60),
+        "2019-09-02T10:16:00",
+    );
+    assert_eq!(
+        runs,
+        vec![
+            utc_time("2019-09-02T10:00:00"),
+            utc_time("2019-09-02T10:05:00"),
+            utc_time("2019-09-02T10:10:00"),
+            utc_time("2019-09-02T10:15:00"),
+        ]
+    );
+    clock.set(utc_time("2019-09-02T10:17:30"));
+    assert_eq!(schedule.delay(&clock), Some(Duration::from_secs(150)));
+}
+
+#[test]
+fn test_schedule_interval_after_clock_jump() {
+    // Runs that were missed while the clock jumped are reported together;
+    // the schedule carries on from the present.
+    let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+    let mut schedule = Schedule::new(
+        Recurrence::Interval(Duration::from_secs(300)),
+        Zone::Local,
+        clock.now(),
+    );
+    let due = schedule.take_due(&clock).unwrap();
+    assert_eq!(due.scheduled, utc_time("2019-09-02T10:00:00"));
+    assert_eq!((due.count, due.missed), (1, 0));
+    clock.advance(Duration::from_secs(17 * 60));
+    let due = schedule.take_due(&clock).unwrap();
+    assert_eq!(due.scheduled, utc_time("2019-09-02T10:15:00"));
+    assert_eq!((due.count, due.missed), (3, 3));
+    assert_eq!(schedule.take_due(&clock), None);
+    assert_eq!(
+        schedule.next_run().unwrap(),
+        utc_time("2019-09-02T10:20:00")
+    );
+}
+
+#[test]
+fn test_schedule_cron_after_suspend() {
+    // A daily run at 02:00, with the host suspended from 01:00 to 10:00.
+    let clock = ManualClock::new(utc_time("2019-09-02T01:00:00"));
+    let mut schedule = Schedule::new(
+        Recurrence::Cron(CronSchedule::parse("0 2 * * *").unwrap()),
+        Zone::Named(Tz::UTC),
+        clock.now(),
+    );
+    clock.set(utc_time("2019-09-02T10:00:00"));
+    let due = schedule.take_due(&clock).unwrap();
+    assert_eq!(due.scheduled, utc_time("2019-09-02T02:00:00"));
+    assert_eq!((due.count, due.missed), (1, 1));
+    // Then for three days.
+    clock.set(utc_time("2019-09-05T12:00:00"));
+    let due = schedule.take_due(&clock).unwrap();
+    assert_eq!(due.scheduled, utc_time("2019-09-05T02:00:00"));
+    assert_eq!((due.count, due.missed), (3, 3));
+    assert_eq!(
+        schedule.next_run().unwrap(),
+        utc_time("2019-09-06T02:00:00")
+    );
+}
+
+#[test]
+fn test_schedule_wait_after_suspend() {
+    // A daily run at 02:00. The wait is never more than a minute, however
+    // far off the run is, since timers don't count time spent suspended.
+    let clock = ManualClock::new(utc_time("2019-09-02T01:00:00"));
+    let mut schedule = Schedule::new(
+        Recurrence::Cron(CronSchedule::parse("0 2 * * *").unwrap()),
+        Zone::Named(Tz::UTC),
+        clock.now(),
+    );
+    assert_eq!(schedule.delay(&clock), Some(Duration::from_secs(3600)));
+    assert_eq!(schedule.wait(&clock), Some(Duration::from_secs(60)));
+    clock.advance(Duration::from_secs(3570));
+    assert_eq!(schedule.wait(&clock), Some(Duration::from_secs(30)));
+    // The host sleeps through three runs, and the first check after it
+    // wakes finds them all due.
+    clock.advance(Duration::from_secs(3 * 86400));
+    assert_eq!(schedule.wait(&clock), Some(Duration::from_secs(0)));
+    let due = schedule.take_due(&clock).unwrap();
+    assert_eq!(due.scheduled, utc_time("2019-09-04T02:00:00"));
+    assert_eq!((due.count, due.missed), (3, 3));
+    assert_eq!(
+        schedule.next_run().unwrap(),
+        utc_time("2019-09-05T02:00:00")
+    );
+}
+
+#[test]
+fn test_catch_up_policy() {
+    let scheduled = Zone::Local.from_utc_datetime(&utc_time("2019-09-02T10:00:00").naive_utc());
+    let on_time = DueRuns {
+        scheduled,
+        count: 1,
+        missed: 0,
+    };
+    let late = DueRuns {
+        scheduled,
+        count: 1,
+        missed: 1,
+    };
+    let several = DueRuns {
+        scheduled,
+        count: 4,
+        missed: 3,
+    };
+    let policies = [CatchUp::None, CatchUp::Once, CatchUp::All];
+    let runs = |due: &DueRuns| policies.iter().map(|p| p.runs(due)).collect::<Vec<u32>>();
+    assert_eq!(runs(&on_time), vec![1, 1, 1]);
+    assert_eq!(runs(&late), vec![0, 1, 1]);
+    assert_eq!(runs(&several), vec![1, 1, 4]);
+    // A day of one second runs is only made up to a limit.
+    let suspended = DueRuns {
+        scheduled,
+        count: 86400,
+        missed: 86400,
+    };
+    assert_eq!(runs(&suspended), vec![0, 1, MAX_CATCH_UP_RUNS]);
+
+    assert_eq!(CatchUp::parse("all"), Ok(CatchUp::All));
+    assert_eq!(
+        CatchUp::parse("some"),
+        Err(String::from(
+            "invalid catch-up policy \"some\", expected none, once or all"
+        ))
+    );
+}
+
+#[test]
+fn test_schedule_cron() {
+    let clock = ManualClock::new(utc_time("2019-09-06T20:00:00"));
+    let mut schedule = Schedule::new(
+        Recurrence::Cron(CronSchedule::parse("15 2 * * MON-FRI").unwrap()),
+        Zone::Named(New_York),
+        clock.now(),
+    );
+    let runs = run_schedule(
+        &mut schedule,
+        &clock,
+        Duration::from_secs(60),
+        "2019-09-11T00:00:00",
+    );
+    // Friday evening to Tuesday night in New York: Monday and Tuesday.
+    assert_eq!(
+        runs,
+        vec![
+            utc_time("2019-09-09T06:15:00"),
+            utc_time("2019-09-10T06:15:00"),
+        ]
+    );
+}
+
+#[test]
+fn test_schedule_restart() {
+    let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+    let mut schedule = Schedule::new(
+        Recurrence::Interval(Duration::from_secs(300)),
+        Zone::Local,
+        clock.now(),
+    );
+    assert!(schedule.take_due(&clock).is_some());
+    // The run took three minutes; the next one is five minutes after that.
+    clock.advance(Duration::from_secs(180));
+    schedule.restart(&clock);
+    assert_eq!(
+        schedule.next_run().unwrap(),
+        utc_time("2019-09-02T10:08:00")
+    );
+}
+
+#[cfg(test)]
+use time::Alignment;
+
+#[test]
+fn test_schedule_aligned_interval() {
+    // Started at 10:02:17, but runs on the five minute marks.
+    let clock = ManualClock::new(utc_time("2019-09-02T10:02:17"));
+    let mut schedule = Schedule::new(
+        Recurrence::Aligned(Duration::from_secs(300), Alignment::Hour),
+        Zone::Named(New_York),
+        clock.now(),
+    );
+    assert_eq!(schedule.delay(&clock), Some(Duration::from_secs(163)));
+    let runs = run_schedule(
+        &mut schedule,
+        &clock,
+        Duration::from_secs(1),
+        "2019-09-02T10:15:00",
+    );
+    assert_eq!(
+        runs,
+        vec![
+            utc_time("2019-09-02T10:05:00"),
+            utc_time("2019-09-02T10:10:00"),
+            utc_time("2019-09-02T10:15:00"),
+        ]
+    );
+}
+
+#[test]
+fn test_schedule_aligned_starts_on_the_mark() {
+    let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+    let schedule = Schedule::new(
+        Recurrence::Aligned(Duration::from_secs(300), Alignment::Day),
+        Zone::Local,
+        clock.now(),
+    );
+    assert_eq!(
+        schedule.next_run().unwrap(),
+        utc_time("2019-09-02T10:00:00")
+    );
+}
+
+#[test]
+fn test_aligned_interval_restarts_each_period() {
+    // Seven minutes doesn't divide into an hour, so the last run in each
+    // hour is at :56 and the count starts again on the hour.
+    let interval = Duration::from_secs(7 * 60);
+    assert_eq!(
+        Alignment::Hour.next_after(interval, &new_york_time("2019-09-02T10:50:00")),
+        Some(new_york_time("2019-09-02T10:56:00"))
+    );
+    assert_eq!(
+        Alignment::Hour.next_after(interval, &new_york_time("2019-09-02T10:56:00")),
+        Some(new_york_time("2019-09-02T11:00:00"))
+    );
+    // Counting from midnight instead, 11:00 is 660 minutes in, which is not
+    // a multiple of 7; the next run is 665 minutes in, at 11:05.
+    assert_eq!(
+        Alignment::Day.next_after(interval, &new_york_time("2019-09-02T10:59:00")),
+        Some(new_york_time("2019-09-02T11:05:00"))
+    );
+    assert_eq!(
+        Alignment::Minute.next_after(
+            Duration::from_secs(15),
+            &new_york_time("2019-09-02T10:59:50")
+        ),
+        Some(new_york_time("2019-09-02T11:00:00"))
+    );
+}
+
+#[test]
+fn test_aligned_interval_fall_back() {
+    // The repeated hour from 01:00 to 02:00 is only run through once.
+    let interval = Duration::from_secs(30 * 60);
+    let mut time = new_york_time("2019-11-03T00:45:00");
+    let mut runs = Vec::new();
+    for _ in 0..4 {
+        time = Alignment::Hour.next_after(interval, &time).unwrap();
+        runs.push(time.with_timezone(&Utc));
+    }
+    assert_eq!(
+        runs,
+        vec![
+            utc_time("2019-11-03T05:00:00"),
+            utc_time("2019-11-03T05:30:00"),
+            utc_time("2019-11-03T07:00:00"),
+            utc_time("2019-11-03T07:30:00"),
+        ]
+    );
+}
+
+#[test]
+fn test_alignment_parse() {
+    assert_eq!(Alignment::parse("hour"), Ok(Alignment::Hour));
+    assert!(Alignment::parse("week").is_err());
+}
+
+#[cfg(test)]
+use calendar::Calendar;
+
+#[cfg(test)]
+fn date(date: &str) -> NaiveDate {
+    NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap()
+}
+
+#[test]
+fn test_calendar_from_yaml() {
+    let calendar = Calendar::from_yaml(
+        "holidays.yaml",
+        concat!(
+            "- 2019-12-25\n",
+            "- 2019-12-30..2020-01-01\n",
+            "- dates: 2019-11-28\n",
+            "  name: Thanksgiving\n",
+        ),
+    )
+    .unwrap();
+    let excluded = |day| calendar.excludes(date(day)).map(|days| days.to_string());
+    assert_eq!(excluded("2019-12-25"), Some(String::from("2019-12-25")));
+    assert_eq!(excluded("2019-12-26"), None);
+    assert_eq!(
+        excluded("2019-12-31"),
+        Some(String::from("2019-12-30 to 2020-01-01"))
+    );
+    assert_eq!(excluded("2020-01-02"), None);
+    assert_eq!(excluded("2019-11-28"), Some(String::from("Thanksgiving")));
+}
+
+#[test]
+fn test_calendar_from_yaml_errors() {
+    assert_eq!(
+        Calendar::from_yaml("holidays.yaml", "- 2019-12-32\n"),
+        Err(String::from(
+            "calendar holidays.yaml: \"2019-12-32\" is not a date in YYYY-MM-DD form"
+        ))
+    );
+    assert_eq!(
+        Calendar::from_yaml("holidays.yaml", "- 2019-12-31..2019-12-24\n"),
+        Err(String::from(
+            "calendar holidays.yaml: \"2019-12-31..2019-12-24\" ends before it starts"
+        ))
+    );
+}
+
+#[test]
+fn test_calendar_from_ics() {
+    let calendar = Calendar::from_ics(
+        "holidays.ics",
+        concat!(
+            "BEGIN:VCALENDAR\r\n",
+            "VERSION:2.0\r\n",
+            "BEGIN:VEVENT\r\n",
+            "DTSTART;VALUE=DATE:20191224\r\n",
+            "DTEND;VALUE=DATE:20191227\r\n",
+            "SUMMARY:Winter\\, part one\r\n",
+            "END:VEVENT\r\n",
+            "BEGIN:VEVENT\r\n",
+            "DTSTART;VALUE=DATE:20180704\r\n",
+            "RRULE:FREQ=YEARLY;COUNT=3\r\n",
+            "SUMMARY:Independence\r\n",
+            "  Day\r\n",
+            "END:VEVENT\r\n",
+            "BEGIN:VEVENT\r\n",
+            "DTSTART:20191011T090000Z\r\n",
+            "DTEND:20191011T170000Z\r\n",
+            "SUMMARY:Offsite\r\n",
+            "END:VEVENT\r\n",
+            "END:VCALENDAR\r\n",
+        ),
+    )
+    .unwrap();
+    let excluded = |day| calendar.excludes(date(day)).map(|days| days.to_string());
+    assert_eq!(excluded("2019-12-23"), None);
+    assert_eq!(
+        excluded("2019-12-26"),
+        Some(String::from("Winter, part one"))
+    );
+    // The end date of an all-day event isn't part of it.
+    assert_eq!(excluded("2019-12-27"), None);
+    assert_eq!(
+        excluded("2018-07-04"),
+        Some(String::from("Independence Day"))
+    );
+    assert_eq!(
+        excluded("2020-07-04"),
+        Some(String::from("Independence Day"))
+    );
+    assert_eq!(excluded("2021-07-04"), None);
+    assert_eq!(excluded("2019-10-11"), Some(String::from("Offsite")));
+}
+
+#[test]
+fn test_calendar_yearly_across_new_year() {
+    let calendar = Calendar::from_ics(
+        "holidays.ics",
+        concat!(
+            "BEGIN:VEVENT\n",
+            "DTSTART;VALUE=DATE:20181231\n",
+            "DTEND;VALUE=DATE:20190102\n",
+            "RRULE:FREQ=YEARLY\n",
+            "END:VEVENT\n",
+        ),
+    )
+    .unwrap();
+    assert!(calendar.excludes(date("2025-01-01")).is_some());
+    assert!(calendar.excludes(date("2025-12-31")).is_some());
+    assert!(calendar.excludes(date("2025-01-02")).is_none());
+    assert!(calendar.excludes(date("2018-01-01")).is_none());
+}
+
+#[test]
+fn test_calendar_from_ics_errors() {
+    assert_eq!(
+        Calendar::from_ics(
+            "holidays.ics",
+            "BEGIN:VEVENT\nDTSTART:20190101\nRRULE:FREQ=WEEKLY\nEND:VEVENT\n"
+        ),
+        Err(String::from(
+            "calendar holidays.ics: line 3: unsupported recurrence rule \"FREQ=WEEKLY\""
+        ))
+    );
+    assert_eq!(
+        Calendar::from_ics(
+            "holidays.ics",
+            "BEGIN:VEVENT\nSUMMARY:Day off\nEND:VEVENT\n"
+        ),
+        Err(String::from(
+            "calendar holidays.ics: line 3: event has no DTSTART"
+        ))
+    );
+}
+
+#[cfg(test)]
+use time::{parse_date_time_in, preview_runs, PreviewEntry, RunConditions};
+
+#[test]
+fn test_run_conditions() {
+    let calendar =
+        Calendar::from_yaml("holidays.yaml", "- dates: 2019-09-02\n  name: Labor Day\n").unwrap();
+    let active = [TimeWindow::parse("09:00-17:00").unwrap()];
+    let blackout = [TimeWindow::parse("12:00-13:00").unwrap()];
+    let conditions = RunConditions {
+        active_windows: &active,
+        blackout_windows: &blackout,
+        exclude_calendar: Some(&calendar),
+    };
+    assert_eq!(
+        conditions.skip_reason(&new_york_time("2019-09-02T10:00:00")),
+        Some(String::from("excluded by holidays.yaml (Labor Day)"))
+    );
+    assert_eq!(
+        conditions.skip_reason(&new_york_time("2019-09-03T08:00:00")),
+        Some(String::from("outside its active windows"))
+    );
+    assert_eq!(
+        conditions.skip_reason(&new_york_time("2019-09-03T12:30:00")),
+        Some(String::from("in blackout window \"12:00-13:00\""))
+    );
+    assert_eq!(
+        conditions.skip_reason(&new_york_time("2019-09-03T10:00:00")),
+        None
+    );
+}
+
+#[test]
+fn test_preview_runs() {
+    let blackout = [TimeWindow::parse("10:10-10:30").unwrap()];
+    let conditions = RunConditions {
+        active_windows: &[],
+        blackout_windows: &blackout,
+        exclude_calendar: None,
+    };
+    let schedule = Schedule::new(
+        Recurrence::Interval(Duration::from_secs(300)),
+        Zone::Named(New_York),
+        utc_time("2019-09-02T14:00:00"),
+    );
+    let at = |time| utc_time(time).with_timezone(&Zone::Named(New_York));
+    assert_eq!(
+        preview_runs(&schedule, &conditions, None, 4),
+        vec![
+            PreviewEntry::Run(at("2019-09-02T14:00:00")),
+            PreviewEntry::Run(at("2019-09-02T14:05:00")),
+            PreviewEntry::Skipped {
+                first: at("2019-09-02T14:10:00"),
+                last: at("2019-09-02T14:25:00"),
+                count: 4,
+                reason: String::from("in blackout window \"10:10-10:30\""),
+            },
+            PreviewEntry::Run(at("2019-09-02T14:30:00")),
+            PreviewEntry::Run(at("2019-09-02T14:35:00")),
+        ]
+    );
+    // Runs at or after the end time aren't listed.
+    assert_eq!(
+        preview_runs(
+            &schedule,
+            &conditions,
+            Some(utc_time("2019-09-02T14:05:00")),
+            4
+        ),
+        vec![PreviewEntry::Run(at("2019-09-02T14:00:00"))]
+    );
+}
+
+#[test]
+fn test_parse_date_time_in() {
+    assert_eq!(
+        parse_date_time_in("2019-09-02T10:00", Zone::Named(New_York)),
+        Ok(utc_time("2019-09-02T14:00:00"))
+    );
+    assert_eq!(
+        parse_date_time_in("2019-09-02T10:00:00Z", Zone::Named(New_York)),
+        Ok(utc_time("2019-09-02T10:00:00"))
+    );
+    assert!(parse_date_time_in("10:00", Zone::Local).is_err());
+}
+
+#[cfg(test)]
+use timer::{TaskTimer, Tick};
+
+// Drives a task timer the way periodic does, moving the clock on by each
+// wait it asks for, until it says the task is finished or the clock passes
+// `until`. Each run takes `run_time`, and with `fixed_delay` the schedule
+// restarts once the runs for a tick are done. Returns when each run
+// started, and why the task finished if it did.
+#[cfg(test)]
+fn run_timer(
+    timer: &mut TaskTimer,
+    conditions: &RunConditions,
+    clock: &ManualClock,
+    until: &str,
+    run_time: Duration,
+    fixed_delay: bool,
+) -> (Vec<DateTime<Utc>>, Option<String>) {
+    let mut runs = Vec::new();
+    while clock.now() <= utc_time(until) {
+        match timer.tick(clock) {
+            Tick::Wait(wait) => clock.advance(wait),
+            Tick::Run { runs: count, .. } => {
+                for _ in 0..count {
+                    if timer.is_finished() {
+                        break;
+                    }
+                    if timer.skip_reason(conditions, clock).is_none() {
+                        runs.push(clock.now());
+                        timer.started();
+                        clock.advance(run_time);
+                    }
+                }
+                if fixed_delay {
+                    timer.restart(clock);
+                }
+            }
+            Tick::Finished(reason) => return (runs, Some(reason)),
+        }
+    }
+    (runs, None)
+}
+
+#[cfg(test)]
+const NO_CONDITIONS: RunConditions = RunConditions {
+    active_windows: &[],
+    blackout_windows: &[],
+    exclude_calendar: None,
+};
+
+#[cfg(test)]
+fn five_minute_timer(
+    clock: &ManualClock,
+    max_runs: Option<u32>,
+    deadline: Option<&str>,
+) -> TaskTimer {
+    TaskTimer::new(
+        Schedule::new(
+            Recurrence::Interval(Duration::from_secs(300)),
+            Zone::Named(Tz::UTC),
+            clock.now(),
+        ),
+        CatchUp::Once,
+        max_runs,
+        deadline.map(utc_time),
+    )
+}
+
+#[test]
+fn test_task_timer_max_runs() {
+    let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+    let mut timer = five_minute_timer(&clock, Some(3), None);
+    let (runs, finished) = run_timer(
+        &mut timer,
+        &NO_CONDITIONS,
+        &clock,
+        "2019-09-02T11:00:00",
+        Duration::from_secs(0),
+        false,
+    );
+    assert_eq!(
+        runs,
+        vec![
+            utc_time("2019-09-02T10:00:00"),
+            utc_time("2019-09-02T10:05:00"),
+            utc_time("2019-09-02T10:10:00"),
+        ]
+    );
+    assert_eq!(finished, Some(String::from("has run 3 time(s)")));
+    assert_eq!(
+        timer.tick(&clock),
+        Tick::Finished(String::from("has run 3 time(s)"))
+    );
+}
+
+#[test]
+fn test_task_timer_end_time() {
+    let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+    let mut timer = five_minute_timer(&clock, None, Some("2019-09-02T10:12:00"));
+    let (runs, finished) = run_timer(
+        &mut timer,
+        &NO_CONDITIONS,
+        &clock,
+        "2019-09-02T11:00:00",
+        Duration::from_secs(0),
+        false,
+    );
+    assert_eq!(
+        runs,
+        vec![
+            utc_time("2019-09-02T10:00:00"),
+            utc_time("2019-09-02T10:05:00"),
+            utc_time("2019-09-02T10:10:00"),
+        ]
+    );
+    assert_eq!(finished, Some(String::from("reached its end time")));
+    // The wait before the next run stopped short at the end time.
+    assert_eq!(clock.now(), utc_time("2019-09-02T10:12:00"));
+}
+
+#[test]
+fn test_task_timer_windows() {
+    let clock = ManualClock::new(utc_time("2019-09-02T08:20:00"));
+    let mut timer = TaskTimer::new(
+        Schedule::new(
+            Recurrence::Interval(Duration::from_secs(20 * 60)),
+            Zone::Named(Tz::UTC),
+            clock.now(),
+        ),
+        CatchUp::Once,
+        None,
+        None,
+    );
+    let active = [TimeWindow::parse("09:00-10:00").unwrap()];
+    let blackout = [TimeWindow::parse("09:15-09:30").unwrap()];
+    let conditions = RunConditions {
+        active_windows: &active,
+        blackout_windows: &blackout,
+        exclude_calendar: None,
+    };
+    let (runs, finished) = run_timer(
+        &mut timer,
+        &conditions,
+        &clock,
+        "2019-09-02T10:30:00",
+        Duration::from_secs(0),
+        false,
+    );
+    assert_eq!(
+        runs,
+        vec![
+            utc_time("2019-09-02T09:00:00"),
+            utc_time("2019-09-02T09:40:00"),
+        ]
+    );
+    assert_eq!(finished, None);
+}
+
+#[test]
+fn test_task_timer_fixed_delay() {
+    let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+    let mut timer = five_minute_timer(&clock, Some(3), None);
+    let (runs, _) = run_timer(
+        &mut timer,
+        &NO_CONDITIONS,
+        &clock,
+        "2019-09-02T11:00:00",
+        Duration::from_secs(120),
+        true,
+    );
+    assert_eq!(
+        runs,
+        vec![
+            utc_time("2019-09-02T10:00:00"),
+            utc_time("2019-09-02T10:07:00"),
+            utc_time("2019-09-02T10:14:00"),
+        ]
+    );
+}
+
+#[test]
+fn test_task_timer_catch_up() {
+    // A one minute task, with the host suspended for ten and a half minutes
+    // after its first run.
+    let policies = [CatchUp::None, CatchUp::Once, CatchUp::All];
+    for (&catch_up, &expected) in policies.iter().zip([0, 1, 10].iter()) {
+        let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+        let mut timer = TaskTimer::new(
+            Schedule::new(
+                Recurrence::Interval(Duration::from_secs(60)),
+                Zone::Named(Tz::UTC),
+                clock.now(),
+            ),
+            catch_up,
+            None,
+            None,
+        );
+        match timer.tick(&clock) {
+            Tick::Run { runs, .. } => assert_eq!(runs, 1),
+            tick => panic!("expected a run, not {:?}", tick),
+        }
+        clock.advance(Duration::from_secs(630));
+        match timer.tick(&clock) {
+            Tick::Run { due, runs } => {
+                assert_eq!(due.scheduled, utc_time("2019-09-02T10:10:00"));
+                assert_eq!((due.count, due.missed), (10, 10));
+                assert_eq!(runs, expected);
+            }
+            tick => panic!("expected a run, not {:?}", tick),
+        }
+        assert_eq!(timer.tick(&clock), Tick::Wait(Duration::from_secs(30)));
+    }
+
+    // A one second task after a 17 hour suspend is only made up so far.
+    let clock = ManualClock::new(utc_time("2019-09-02T10:00:00"));
+    let mut timer = TaskTimer::new(
+        Schedule::new(
+            Recurrence::Interval(Duration::from_secs(1)),
+            Zone::Named(Tz::UTC),
+            clock.now(),
+        ),
+        CatchUp::All,
+        None,
+        None,
+    );
+    timer.tick(&clock);
+    clock.advance(Duration::from_secs(17 * 3600));
+    match timer.tick(&clock) {
+        Tick::Run { due, runs } => {
+            assert_eq!(due.count, 17 * 3600);
+            assert_eq!(runs, MAX_CATCH_UP_RUNS);
+        }
+        tick => panic!("expected a run, not {:?}", tick),
+    }
+}
+
+#[cfg(test)]
+use signal::{parse_signal, signal_name};
+
+#[test]
+fn test_parse_signal() {
+    assert_eq!(parse_signal("SIGTERM"), Ok(libc::SIGTERM));
+    assert_eq!(parse_signal("int"), Ok(libc::SIGINT));
+    assert_eq!(parse_signal("9"), Ok(libc::SIGKILL));
+    assert_eq!(
+        parse_signal("SIGBOGUS"),
+        Err(String::from("unknown signal \"SIGBOGUS\""))
+    );
+    assert_eq!(
+        parse_signal("999"),
+        Err(String::from("unknown signal number 999"))
+    );
+    assert_eq!(signal_name(libc::SIGXCPU), Some("SIGXCPU"));
+}
+
+#[cfg(test)]
+use env::{parse_env_file, parse_env_var};
+
+#[test]
+fn test_parse_env_file() {
+    let vars = parse_env_file(
+        "test.env",
+        concat!(
+            "# database settings\n",
+            "\n",
+            "DB_HOST=db.example.com\n",
+            "export DB_PORT = 5432 # the default\n",
+            "GREETING=\"hello \\\"world\\\"\\nbye\"\n",
+            "LITERAL='no $expansion\\n here' # comment\n",
+            "EMPTY=\n",
+        ),
+    )
+    .unwrap();
+    assert_eq!(
+        vars,
+        vec![
+            (String::from("DB_HOST"), String::from("db.example.com")),
+            (String::from("DB_PORT"), String::from("5432")),
+            (
+                String::from("GREETING"),
+                String::from("hello \"world\"\nbye")
+            ),
+            (
+                String::from("LITERAL"),
+                String::from("no $expansion\\n here")
+            ),
+            (String::from("EMPTY"), String::new()),
+        ]
+    );
+}
+
+#[test]
+fn test_parse_env_file_errors() {
+    assert_eq!(
+        parse_env_file("test.env", "A=1\nJUST_A_NAME\n"),
+        Err(String::from(
+            "env file test.env: line 2: \"JUST_A_NAME\" is not of the form NAME=value"
+        ))
+    );
+    assert_eq!(
+        parse_env_file("test.env", "1ST=x"),
+        Err(String::from(
+            "env file test.env: line 1: invalid variable name \"1ST\""
+        ))
+    );
+    assert_eq!(
+        parse_env_file("test.env", "A=\"unterminated"),
+        Err(String::from(
+            "env file test.env: line 1: missing closing quote"
+        ))
+    );
+    assert_eq!(
+        parse_env_file("test.env", "A='x' y"),
+        Err(String::from(
+            "env file test.env: line 1: unexpected \"y\" after closing quote"
+        ))
+    );
+}
+
+#[test]
+fn test_parse_env_var() {
+    assert_eq!(
+        parse_env_var("KEY=a=b"),
+        Ok((String::from("KEY"), String::from("a=b")))
+    );
+    assert!(parse_env_var("KEY").is_err());
+    assert!(parse_env_var("BAD-NAME=1").is_err());
+}
+
+#[cfg(test)]
+use command::TaskCommand;
+
+#[cfg(test)]
+fn args(args: &[&str]) -> Vec<String> {
+    args.iter().map(|arg| arg.to_string()).collect()
+}
+
+#[test]
+fn test_task_command_from_config() {
+    assert_eq!(
+        serde_yaml::from_str::<TaskCommand>("echo 'hello world'").unwrap(),
+        TaskCommand::Line(String::from("echo 'hello world'"))
+    );
+    assert_eq!(
+        serde_yaml::from_str::<TaskCommand>("[echo, hello world]").unwrap(),
+        TaskCommand::Args(args(&["echo", "hello world"]))
+    );
+    assert!(serde_yaml::from_str::<TaskCommand>("true").is_err());
+}
+
+#[test]
+fn test_task_command_argv() {
+    let line = TaskCommand::Line(String::from("echo 'hello world' $HOME"));
+    assert_eq!(
+        line.argv(false, None),
+        Ok(args(&["echo", "hello world", "$HOME"]))
+    );
+    assert_eq!(
+        line.argv(true, None),
+        Ok(args(&["/bin/sh", "-c", "echo 'hello world' $HOME"]))
+    );
+    assert_eq!(
+        line.argv(true, Some("/bin/bash")),
+        Ok(args(&["/bin/bash", "-c", "echo 'hello world' $HOME"]))
+    );
+    let list = TaskCommand::Args(args(&["ls", "-l"]));
+    assert_eq!(list.argv(false, None), Ok(args(&["ls", "-l"])));
+    assert_eq!(
+        list.argv(true, None).unwrap_err(),
+        "shell needs cmd to be a string, not a list"
+    );
+}
+
+#[test]
+fn test_task_command_argv_errors() {
+    let argv = |line: &str| TaskCommand::Line(String::from(line)).argv(false, None);
+    assert_eq!(argv("echo 'oops").unwrap_err(), "mismatched quotes in cmd");
+    assert_eq!(argv("").unwrap_err(), "cmd is empty");
+    assert_eq!(argv("''").unwrap_err(), "cmd is empty");
+    assert_eq!(
+        TaskCommand::Args(Vec::new()).argv(false, None).unwrap_err(),
+        "cmd is empty"
+    );
+}
+
+#[cfg(test)]
+use credentials::Credentials;
+
+#[test]
+fn test_credentials_resolve() {
+    let root = Credentials::resolve(Some("root"), None, None).unwrap();
+    assert_eq!((root.uid, root.gid), (Some(0), Some(0)));
+    assert_eq!(
+        Credentials::resolve(Some("0"), Some("0"), Some(&[String::from("root")])),
+        Ok(Credentials {
+            uid: Some(0),
+            gid: Some(0),
+            groups: Some(vec![0]),
+        })
+    );
+    // Unknown IDs are allowed, but not unknown names.
+    let unknown = Credentials::resolve(Some("54321"), Some("54321"), None).unwrap();
+    assert_eq!((unknown.uid, unknown.gid), (Some(54321), Some(54321)));
+    assert_eq!(
+        Credentials::resolve(Some("no-such-user"), None, None),
+        Err(String::from("unknown user \"no-such-user\""))
+    );
+    assert_eq!(
+        Credentials::resolve(None, Some("no-such-group"), None),
+        Err(String::from("unknown group \"no-such-group\""))
+    );
+    assert!(Credentials::resolve(None, None, None).unwrap().is_empty());
+}
+
+#[test]
+fn test_credentials_unknown_user_id_needs_group() {
+    assert_eq!(
+        Credentials::resolve(Some("54321"), None, None),
+        Err(String::from("user 54321 has no passwd entry; set group"))
+    );
+    assert_eq!(
+        Credentials::resolve(Some("54321"), None, Some(&[String::from("54321")])),
+        Err(String::from("user 54321 has no passwd entry; set group"))
+    );
+    let unknown = Credentials::resolve(Some("54321"), Some("0"), None).unwrap();
+    assert_eq!((unknown.uid, unknown.gid), (Some(54321), Some(0)));
+}
+
+#[cfg(test)]
+use limits::{limit_signal_reason, parse_size, Limits, Resource};
+
+#[test]
+fn test_parse_size() {
+    assert_eq!(parse_size("4096"), Ok(4096));
+    assert_eq!(parse_size("512K"), Ok(512 * 1024));
+    assert_eq!(parse_size("2G"), Ok(2 << 30));
+    assert_eq!(parse_size("1MiB"), Ok(1 << 20));
+    assert_eq!(parse_size("10mb"), Ok(10 << 20));
+    assert_eq!(
+        parse_size("2X"),
+        Err(String::from(
+            "\"2X\" is not a size such as \"512M\" or \"2G\""
+        ))
+    );
+    assert!(parse_size("99999999999T").is_err());
+}
+
+#[test]
+fn test_limits() {
+    let mut limits = Limits::default();
+    assert!(limits.is_empty());
+    limits.set("address_space", "1G").unwrap();
+    limits.set("cpu", "1m30s").unwrap();
+    limits.set("open_files", "256").unwrap();
+    limits.set("open_files", "512").unwrap();
+    limits.set("core_size", "0").unwrap();
+    limits.set("file_size", "unlimited").unwrap();
+    assert_eq!(limits.get(Resource::AddressSpace), Some(1 << 30));
+    assert_eq!(limits.get(Resource::Cpu), Some(90));
+    assert_eq!(limits.get(Resource::OpenFiles), Some(512));
+    assert_eq!(limits.get(Resource::CoreSize), Some(0));
+    assert_eq!(limits.get(Resource::FileSize), Some(libc::RLIM_INFINITY));
+    assert_eq!(limits.get(Resource::Processes), None);
+
+    assert_eq!(
+        limits.set("memory", "1G"),
+        Err(String::from(
+            "unknown limit \"memory\", expected address_space, cpu, open_files, \
+             processes, core_size or file_size"
+        ))
+    );
+    assert_eq!(
+        limits.set("processes", "lots"),
+        Err(String::from(
+            "invalid processes limit: \"lots\" is not a number"
+        ))
+    );
+    assert_eq!(
+        limit_signal_reason(libc::SIGXCPU),
+        Some("CPU time limit exceeded")
+    );
+    assert_eq!(limit_signal_reason(libc::SIGTERM), None);
+}
+
+#[cfg(test)]
+use priority::{CpuSet, IoClass, Priority};
+
+#[test]
+fn test_cpu_set_parse() {
+    assert_eq!(CpuSet::parse("0-3,6").unwrap().cpus(), &[0, 1, 2, 3, 6]);
+    assert_eq!(CpuSet::parse("2, 1, 2").unwrap().cpus(), &[1, 2]);
+    assert_eq!(
+        CpuSet::parse("3-1"),
+        Err(String::from("invalid CPU list \"3-1\""))
+    );
+    assert!(CpuSet::parse("a").is_err());
+    assert!(CpuSet::parse("").is_err());
+    assert_eq!(
+        CpuSet::from_cpus(vec![100000]),
+        Err(String::from("CPU 100000 is out of range"))
+    );
+}
+
+#[test]
+fn test_priority_check() {
+    assert!(Priority::default().is_empty());
+    assert_eq!(IoClass::parse("idle"), Ok(IoClass::Idle));
+    assert!(IoClass::parse("fast").is_err());
+    let check = |priority: Priority| priority.validate().and_then(|_| priority.check());
+    assert_eq!(
+        check(Priority {
+            nice: Some(20),
+            ..Priority::default()
+        }),
+        Err(String::from("nice must be between -20 and 19"))
+    );
+    assert_eq!(
+        check(Priority {
+            io_priority: Some(8),
+            ..Priority::default()
+        }),
+        Err(String::from("io_priority must be between 0 and 7"))
+    );
+    assert_eq!(
+        check(Priority {
+            io_class: Some(IoClass::Idle),
+            io_priority: Some(2),
+            ..Priority::default()
+        }),
+        Err(String::from(
+            "io_priority can't be used with the idle io_class"
+        ))
+    );
+    assert_eq!(
+        check(Priority {
+            nice: Some(19),
+            io_class: Some(IoClass::BestEffort),
+            io_priority: Some(7),
+            cpu_affinity: Some(CpuSet::parse("0").unwrap()),
+        }),
+        Ok(())
+    );
+}
+
+#[cfg(test)]
+use bytes::BytesMut;
+#[cfg(test)]
+use output::LineCodec;
+#[cfg(test)]
+use tokio_codec::Decoder;
+
+#[test]
+fn test_line_codec() {
+    let mut codec = LineCodec;
+    let mut buf = BytesMut::from(&b"first\r\nsecond\nbad \xff byte\nno newline"[..]);
+    assert_eq!(codec.decode(&mut buf).unwrap(), Some(String::from("first")));
+    assert_eq!(
+        codec.decode(&mut buf).unwrap(),
+        Some(String::from("second"))
+    );
+    assert_eq!(
+        codec.decode(&mut buf).unwrap(),
+        Some(String::from("bad \u{fffd} byte"))
+    );
+    assert_eq!(codec.decode(&mut buf).unwrap(), None);
+    assert_eq!(
+        codec.decode_eof(&mut buf).unwrap(),
+        Some(String::from("no newline"))
+    );
+    assert_eq!(codec.decode_eof(&mut buf).unwrap(), None);
+
+    let mut long = BytesMut::from(vec![b'x'; 100 * 1024]);
+    assert_eq!(codec.decode(&mut long).unwrap().unwrap().len(), 64 * 1024);
+    assert_eq!(codec.decode(&mut long).unwrap(), None);
+}
+
+#[cfg(test)]
+use flate2::read::GzDecoder;
+#[cfg(test)]
+use output::{log_file_path, LogFile, Rotation};
+#[cfg(test)]
+use std::fs;
+#[cfg(test)]
+use std::io::Read;
+#[cfg(test)]
+use std::path::Path;
+
+// A fresh directory for a test's files.
+#[cfg(test)]
+fn test_dir(name: &str) -> String {
+    let dir = std::env::temp_dir().join(format!("periodic-{}-{}", name, std::process::id()));
+    drop(fs::remove_dir_all(&dir));
+    fs::create_dir_all(&dir).unwrap();
+    dir.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+fn read_file(path: &str) -> String {
+    fs::read_to_string(path).unwrap()
+}
+
+#[test]
+fn test_log_file_path() {
+    assert_eq!(
+        log_file_path(
+            "/var/log/{name}-{date}.log",
+            "db/backup",
+            date("2019-09-02")
+        ),
+        "/var/log/db_backup-2019-09-02.log"
+    );
+    assert_eq!(
+        log_file_path("out.log", "backup", date("2019-09-02")),
+        "out.log"
+    );
+}
+
+#[test]
+fn test_rotation_set() {
+    let mut rotation = Rotation::default();
+    rotation.set("max_size", "10K").unwrap();
+    rotation.set("daily", "true").unwrap();
+    rotation.set("keep", "3").unwrap();
+    assert_eq!(
+        rotation,
+        Rotation {
+            max_size: Some(10 * 1024),
+            daily: true,
+            keep: 3,
+            compress: false,
+        }
+    );
+    assert_eq!(
+        rotation.set("compress", "yes"),
+        Err(String::from("compress must be true or false"))
+    );
+    assert!(rotation.set("hourly", "true").is_err());
+}
+
+#[test]
+fn test_log_file_rotates_by_size() {
+    let dir = test_dir("size");
+    let template = format!("{}/{{name}}.log", dir);
+    let rotation = Rotation {
+        max_size: Some(10),
+        keep: 2,
+        ..Rotation::default()
+    };
+    let today = date("2019-09-02");
+    let mut log = LogFile::open(&template, "task", Some(rotation), today).unwrap();
+    for line in &["one", "two", "three", "four", "five", "six"] {
+        log.write_line(line, today).unwrap();
     }
+    let path = format!("{}/task.log", dir);
+    assert_eq!(log.path(), path);
+    assert_eq!(read_file(&path), "six\n");
+    assert_eq!(read_file(&format!("{}.1", path)), "four\nfive\n");
+    assert_eq!(read_file(&format!("{}.2", path)), "three\n");
+    assert!(!Path::new(&format!("{}.3", path)).exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_log_file_rotates_daily_with_compression() {
+    let dir = test_dir("daily");
+    let template = format!("{}/task.log", dir);
+    let rotation = Rotation {
+        daily: true,
+        compress: true,
+        ..Rotation::default()
+    };
+    let mut log = LogFile::open(&template, "task", Some(rotation), date("2019-09-02")).unwrap();
+    log.write_line("monday", date("2019-09-02")).unwrap();
+    log.write_line("more monday", date("2019-09-02")).unwrap();
+    log.write_line("tuesday", date("2019-09-03")).unwrap();
+    assert_eq!(read_file(&template), "tuesday\n");
+    let mut rotated = String::new();
+    GzDecoder::new(fs::File::open(format!("{}.1.gz", template)).unwrap())
+        .read_to_string(&mut rotated)
+        .unwrap();
+    assert_eq!(rotated, "monday\nmore monday\n");
+    assert!(!Path::new(&format!("{}.1", template)).exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_log_file_follows_dated_path() {
+    let dir = test_dir("dated");
+    let template = format!("{}/task-{{date}}.log", dir);
+    let mut log = LogFile::open(&template, "task", None, date("2019-09-02")).unwrap();
+    log.write_line("monday", date("2019-09-02")).unwrap();
+    log.write_line("tuesday", date("2019-09-03")).unwrap();
+    assert_eq!(
+        read_file(&format!("{}/task-2019-09-02.log", dir)),
+        "monday\n"
+    );
+    assert_eq!(
+        read_file(&format!("{}/task-2019-09-03.log", dir)),
+        "tuesday\n"
+    );
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(test)]
+use history::History;
+
+#[cfg(test)]
+fn output_lines(history: &History, run: usize) -> Vec<String> {
+    history.runs()[run]
+        .output
+        .iter()
+        .map(|output| format!("{}: {}", output.stream, output.line))
+        .collect()
+}
+
+#[test]
+fn test_history_keeps_recent_runs() {
+    let mut history = History::new(2, 1024);
+    history.start(100, utc_time("2019-09-02T16:45:00"));
+    history.start(101, utc_time("2019-09-02T16:46:00"));
+    history.finish(
+        100,
+        utc_time("2019-09-02T16:46:30"),
+        String::from("exit status 1"),
+        true,
+    );
+    history.start(102, utc_time("2019-09-02T16:47:00"));
+    let pids = history
+        .runs()
+        .iter()
+        .map(|run| run.pid)
+        .collect::<Vec<u32>>();
+    assert_eq!(pids, vec![101, 102]);
+    assert_eq!(history.runs()[0].status, None);
+    assert_eq!(history.runs()[0].duration(), None);
+
+    history.finish(
+        101,
+        utc_time("2019-09-02T16:46:05"),
+        String::from("exit status 0"),
+        false,
+    );
+    let run = &history.runs()[0];
+    assert_eq!(run.status, Some(String::from("exit status 0")));
+    assert_eq!(run.duration(), Some(Duration::from_secs(5)));
+
+    let mut none = History::new(0, 1024);
+    none.start(100, utc_time("2019-09-02T16:45:00"));
+    assert!(none.runs().is_empty());
+}
+
+#[test]
+fn test_history_keeps_end_of_output() {
+    let mut history = History::new(2, 12);
+    history.start(100, utc_time("2019-09-02T16:45:00"));
+    history.add_output(100, "out", "one");
+    history.add_output(100, "err", "two");
+    history.add_output(100, "out", "three");
+    assert_eq!(output_lines(&history, 0), vec!["err: two", "out: three"]);
+    assert!(history.runs()[0].output_truncated);
+
+    // Output for a finished run is ignored.
+    history.start(101, utc_time("2019-09-02T16:46:00"));
+    history.finish(
+        100,
+        utc_time("2019-09-02T16:45:01"),
+        String::from("exit status 0"),
+        false,
+    );
+    history.add_output(100, "out", "late");
+    assert_eq!(output_lines(&history, 0), vec!["err: two", "out: three"]);
+
+    history.add_output(101, "out", "a line that's far too long");
+    assert_eq!(output_lines(&history, 1), vec!["out: ar too long"]);
+    assert!(history.runs()[1].output_truncated);
 }
@@ -1,90 +1,1266 @@
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
 use std::time::Duration;
 
 use regex::Regex;
 use chrono::prelude::*;
+use chrono::LocalResult;
+use chrono_tz::Tz as NamedZone;
+use rand::Rng;
 
-const DAY_SECONDS: u64 = (60 * 60 * 24);
+use calendar::Calendar;
+use clock::Clock;
 
-pub fn get_start_delay_from_next(now: DateTime<Local>, next: &str) -> Result<Duration, String> {
-    let re = Regex::new(r"(?P<interval>hour|minute)(\+(?P<after>\d{1,2}))?$").unwrap();
-    match re.captures(next) {
-        Some(time) => {
-            let after = match time.name("after") {
-                Some(num) => num.as_str().parse::<u32>().unwrap(),
-                None => 0,
+const DAY_SECONDS: u64 = 60 * 60 * 24;
+
+/// The time zone a task's schedule is computed in: either the system's
+/// local zone or a named IANA zone such as "Europe/Berlin".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Zone {
+    Local,
+    Named(NamedZone),
+}
+
+impl Zone {
+    pub fn parse(name: &str) -> Result<Zone, String> {
+        if name.eq_ignore_ascii_case("local") {
+            Ok(Zone::Local)
+        } else {
+            name.parse::<NamedZone>()
+                .map(Zone::Named)
+                .map_err(|_| format!("unknown time zone \"{}\"", name))
+        }
+    }
+
+    pub fn now(&self, clock: &dyn Clock) -> DateTime<Zone> {
+        clock.now().with_timezone(self)
+    }
+}
+
+impl fmt::Display for Zone {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Zone::Local => f.write_str("local"),
+            Zone::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+
+/// The UTC offset in effect at some time in a `Zone`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ZoneOffset {
+    zone: Zone,
+    offset: FixedOffset,
+}
+
+impl Offset for ZoneOffset {
+    fn fix(&self) -> FixedOffset {
+        self.offset
+    }
+}
+
+impl fmt::Display for ZoneOffset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.offset.fmt(f)
+    }
+}
+
+impl TimeZone for Zone {
+    type Offset = ZoneOffset;
+
+    fn from_offset(offset: &ZoneOffset) -> Zone {
+        offset.zone
+    }
+
+    fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<ZoneOffset> {
+        self.offset_from_local_datetime(&local.and_hms_opt(0, 0, 0).unwrap())
+    }
+
+    fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<ZoneOffset> {
+        let zone = *self;
+        let offsets = match zone {
+            Zone::Local => Local.offset_from_local_datetime(local),
+            Zone::Named(tz) => tz
+                .offset_from_local_datetime(local)
+                .map(|offset| offset.fix()),
+        };
+        offsets.map(|offset| ZoneOffset { zone, offset })
+    }
+
+    fn offset_from_utc_date(&self, utc: &NaiveDate) -> ZoneOffset {
+        self.offset_from_utc_datetime(&utc.and_hms_opt(0, 0, 0).unwrap())
+    }
+
+    fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> ZoneOffset {
+        let offset = match *self {
+            Zone::Local => Local.offset_from_utc_datetime(utc),
+            Zone::Named(tz) => tz.offset_from_utc_datetime(utc).fix(),
+        };
+        ZoneOffset {
+            zone: *self,
+            offset,
+        }
+    }
+}
+
+/// Converts a wall-clock time in `tz` to an actual point in time. A time
+/// that is skipped when the clocks go forward is moved forward by the
+/// length of the gap, so 02:30 on a night that jumps from 02:00 to 03:00
+/// becomes 03:30. A time that happens twice when the clocks go back
+/// resolves to the first occurrence.
+pub fn resolve_local<Tz: TimeZone>(tz: &Tz, local: &NaiveDateTime) -> DateTime<Tz> {
+    match tz.from_local_datetime(local) {
+        LocalResult::Single(time) => time,
+        LocalResult::Ambiguous(earliest, _) => earliest,
+        LocalResult::None => {
+            // Interpret the time using the offset from before the gap,
+            // assuming that no two transitions happen within a day.
+            let before = tz
+                .offset_from_utc_datetime(&(*local - chrono::Duration::days(1)))
+                .fix();
+            tz.from_utc_datetime(&(*local - before))
+        }
+    }
+}
+
+fn delay_until<Tz: TimeZone>(now: &DateTime<Tz>, start_at: &DateTime<Tz>) -> Duration {
+    start_at
+        .clone()
+        .signed_duration_since(now)
+        .to_std()
+        .unwrap_or_else(|_| Duration::from_secs(0))
+}
+
+const DATE_TIME_FORMATS: [&str; 4] = [
+    "%Y-%m-%d %H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%dT%H:%M:%S",
+];
+
+const WEEKDAYS: [(&str, Weekday); 7] = [
+    ("monday", Weekday::Mon),
+    ("tuesday", Weekday::Tue),
+    ("wednesday", Weekday::Wed),
+    ("thursday", Weekday::Thu),
+    ("friday", Weekday::Fri),
+    ("saturday", Weekday::Sat),
+    ("sunday", Weekday::Sun),
+];
+
+/// When a task should first run, as given by `-s` or a task's start_time.
+/// Times of day are wall-clock times in the task's time zone.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StartSpec {
+    /// "HH:MM" or "day(+HH:MM)": the next time the clock reads this.
+    TimeOfDay(NaiveTime),
+    /// "hour(+MM)": the next time the clock reaches this many minutes past
+    /// the hour.
+    NextHour(u32),
+    /// "minute(+SS)": the next time the clock reaches this many seconds past
+    /// the minute.
+    NextMinute(u32),
+    /// "monday(HH:MM)": the next time it's this time of day, or midnight,
+    /// on this day of the week.
+    Weekday(Weekday, NaiveTime),
+    /// "month(+N)": midnight at the start of the Nth month from now, or of
+    /// next month.
+    NextMonth(u32),
+    /// "in 10m": this long from now.
+    After(Duration),
+    /// An ISO-8601 date and time such as "2019-09-02T10:00".
+    At(NaiveDateTime),
+    /// An ISO-8601 date and time with a UTC offset, such as
+    /// "2019-09-02T10:00:00+02:00".
+    AtInstant(DateTime<FixedOffset>),
+}
+
+// Parses "HH:MM", with nothing before or after it.
+fn parse_time_of_day(token: &str) -> Result<NaiveTime, String> {
+    let re = Regex::new(r"^(?P<hour>\d{1,2}):(?P<minute>\d{2})$").unwrap();
+    re.captures(token)
+        .and_then(|time| {
+            NaiveTime::from_hms_opt(
+                time["hour"].parse().unwrap(),
+                time["minute"].parse().unwrap(),
+                0,
+            )
+        })
+        .ok_or_else(|| format!("\"{}\" is not a time of day in HH:MM form", token))
+}
+
+// Parses the number after the "+" in "hour+MM" and the like.
+fn parse_anchor_offset(token: &str, offset: &str, max: u32, what: &str) -> Result<u32, String> {
+    match offset.parse::<u32>() {
+        Ok(value) if value <= max => Ok(value),
+        _ => Err(format!(
+            "\"{}\" in \"{}\" is not a number of {} from 0 to {}",
+            offset, token, what, max
+        )),
+    }
+}
+
+fn parse_date_time(value: &str) -> Option<StartSpec> {
+    DATE_TIME_FORMATS
+        .iter()
+        .filter_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(StartSpec::At)
+        .next()
+        .or_else(|| {
+            DateTime::parse_from_rfc3339(value)
+                .or_else(|_| DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M%:z"))
+                .ok()
+                .map(StartSpec::AtInstant)
+        })
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| StartSpec::At(date.and_hms_opt(0, 0, 0).unwrap()))
+        })
+}
+
+fn looks_like_date(token: &str) -> bool {
+    token.len() >= 10
+        && token.as_bytes()[4] == b'-'
+        && token[..4].bytes().all(|b| b.is_ascii_digit())
+}
+
+impl StartSpec {
+    pub fn parse(start_at: &str) -> Result<StartSpec, String> {
+        StartSpec::parse_tokens(
+            start_at.trim(),
+            &start_at.split_whitespace().collect::<Vec<_>>(),
+        )
+        .map_err(|e| format!("invalid start time \"{}\": {}", start_at, e))
+    }
+
+    fn parse_tokens(start_at: &str, tokens: &[&str]) -> Result<StartSpec, String> {
+        let first = match tokens.first() {
+            Some(first) => first.to_ascii_lowercase(),
+            None => return Err(String::from("it is empty")),
+        };
+        if first == "in" {
+            if tokens.len() == 1 {
+                return Err(String::from("expected a duration after \"in\""));
+            }
+            let duration = parse_duration(&tokens[1..].concat()).map_err(|e| e.to_string())?;
+            return match chrono::Duration::from_std(duration) {
+                Ok(_) => Ok(StartSpec::After(duration)),
+                Err(_) => Err(format!(
+                    "\"{}\" is too far in the future",
+                    tokens[1..].join(" ")
+                )),
+            };
+        }
+        if looks_like_date(&first) {
+            return parse_date_time(&tokens.join(" "))
+                .ok_or_else(|| format!("\"{}\" is not an ISO-8601 date and time", start_at));
+        }
+        if let Some(&(_, weekday)) = WEEKDAYS
+            .iter()
+            .find(|&&(name, _)| first == name || first == name[..3])
+        {
+            return match tokens.len() {
+                1 => Ok(StartSpec::Weekday(weekday, NaiveTime::MIN)),
+                2 => parse_time_of_day(tokens[1]).map(|time| StartSpec::Weekday(weekday, time)),
+                _ => Err(format!("unexpected \"{}\"", tokens[2])),
             };
+        }
+        let (anchor, offset) = match first.find('+') {
+            Some(plus) => (&first[..plus], Some(&first[plus + 1..])),
+            None => (&first[..], None),
+        };
+        let spec = match (anchor, offset) {
+            ("hour", None) => Ok(StartSpec::NextHour(0)),
+            ("hour", Some(minutes)) => {
+                parse_anchor_offset(tokens[0], minutes, 59, "minutes").map(StartSpec::NextHour)
+            }
+            ("minute", None) => Ok(StartSpec::NextMinute(0)),
+            ("minute", Some(seconds)) => {
+                parse_anchor_offset(tokens[0], seconds, 59, "seconds").map(StartSpec::NextMinute)
+            }
+            ("day", None) => Ok(StartSpec::TimeOfDay(NaiveTime::MIN)),
+            ("day", Some(time)) => parse_time_of_day(time).map(StartSpec::TimeOfDay),
+            ("month", None) => Ok(StartSpec::NextMonth(1)),
+            ("month", Some(months)) => match months.parse::<u32>() {
+                Ok(months) if (1..=1200).contains(&months) => Ok(StartSpec::NextMonth(months)),
+                _ => Err(format!(
+                    "\"{}\" in \"{}\" is not a number of months from 1 to 1200",
+                    months, tokens[0]
+                )),
+            },
+            _ if first.contains(':') => parse_time_of_day(tokens[0]).map(StartSpec::TimeOfDay),
+            _ => Err(format!("unknown word \"{}\"", tokens[0])),
+        }?;
+        match tokens.get(1) {
+            Some(extra) => Err(format!("unexpected \"{}\"", extra)),
+            None => Ok(spec),
+        }
+    }
 
-            let start_at = if &time["interval"] == "hour" {
-                // Start some number of minutes after the hour.
-                let start_minute = now.minute();
-                if start_minute <= after {
-                    // The start minute has not been reached within the current hour
-                    // so the difference the requested minute and now is the delay.
-                    now + chrono::Duration::minutes((after - start_minute) as i64)
-                } else {
-                    // The start minute has already been reached in the current hour, so
-                    // advance to the next hour after the present one, then add the "after"
-                    // value as minutes.
-                    let next = now + chrono::Duration::hours(1);
-                    Local
-                        .ymd(next.year(), next.month(), next.day())
-                        .and_hms(next.hour(), after, 0)
-                }
+    /// The time at which a task with this start time should first run, if
+    /// it's set up at `now`. That's never earlier than `now`.
+    pub fn start_after<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> DateTime<Tz> {
+        // Work in wall-clock time, so that the start is at the requested time
+        // of day even across DST changes.
+        let tz = now.timezone();
+        let local = now.naive_local();
+        let at_or_after = |candidate: NaiveDateTime, step: chrono::Duration| {
+            let start = resolve_local(&tz, &candidate);
+            if start >= *now {
+                start
             } else {
-                // Start some number of seconds after the minute.
-                let start_second = now.second();
-                if start_second <= after {
-                    // The start second has not been reached within the current minute
-                    // so the difference the requested second and now is the delay.
-                    now + chrono::Duration::seconds((after - start_second) as i64)
+                resolve_local(&tz, &(candidate + step))
+            }
+        };
+        let start = match *self {
+            StartSpec::TimeOfDay(time) => {
+                // Tomorrow isn't always 24 hours later.
+                at_or_after(local.date().and_time(time), chrono::Duration::days(1))
+            }
+            StartSpec::NextHour(minute) => at_or_after(
+                local.date().and_hms_opt(local.hour(), minute, 0).unwrap(),
+                chrono::Duration::hours(1),
+            ),
+            StartSpec::NextMinute(second) => at_or_after(
+                local
+                    .date()
+                    .and_hms_opt(local.hour(), local.minute(), second)
+                    .unwrap(),
+                chrono::Duration::minutes(1),
+            ),
+            StartSpec::Weekday(weekday, time) => {
+                let today = local.date();
+                let days_ahead = (7 + weekday.num_days_from_monday()
+                    - today.weekday().num_days_from_monday())
+                    % 7;
+                at_or_after(
+                    (today + chrono::Duration::days(i64::from(days_ahead))).and_time(time),
+                    chrono::Duration::weeks(1),
+                )
+            }
+            StartSpec::NextMonth(months) => {
+                let month0 = local.year() * 12 + local.month0() as i32 + months as i32;
+                let first = NaiveDate::from_ymd_opt(month0 / 12, month0 as u32 % 12 + 1, 1)
+                    .unwrap_or(NaiveDate::MAX);
+                resolve_local(&tz, &first.and_time(NaiveTime::MIN))
+            }
+            // A start too far off to represent is as late as can be, rather
+            // than straight away.
+            StartSpec::After(duration) => chrono::Duration::from_std(duration)
+                .ok()
+                .and_then(|duration| now.clone().checked_add_signed(duration))
+                .unwrap_or_else(|| resolve_local(&tz, &NaiveDate::MAX.and_time(NaiveTime::MIN))),
+            StartSpec::At(local) => resolve_local(&tz, &local),
+            StartSpec::AtInstant(instant) => instant.with_timezone(&tz),
+        };
+        // A time that's already passed means starting straight away.
+        if start < *now {
+            now.clone()
+        } else {
+            start
+        }
+    }
+
+    /// How long to wait from `now` until the first run.
+    pub fn delay<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Duration {
+        delay_until(now, &self.start_after(now))
+    }
+}
+
+/// Computes the delay until `next`, which is one of the "hour(+MM)" and
+/// "minute(+SS)" forms.
+pub fn get_start_delay_from_next<Tz: TimeZone>(
+    now: DateTime<Tz>,
+    next: &str,
+) -> Result<Duration, String> {
+    match StartSpec::parse(next)? {
+        spec @ StartSpec::NextHour(_) | spec @ StartSpec::NextMinute(_) => Ok(spec.delay(&now)),
+        _ => Err(format!("invalid format for start time: {}", next)),
+    }
+}
+
+/// Computes the delay until `start_at`, which is "HH:MM".
+pub fn get_start_delay_from_hh_mm<Tz: TimeZone>(
+    now: DateTime<Tz>,
+    start_at: &str,
+) -> Result<Duration, String> {
+    match StartSpec::parse(start_at)? {
+        spec @ StartSpec::TimeOfDay(_) => Ok(spec.delay(&now)),
+        _ => Err(format!("invalid format for start time: {}", start_at)),
+    }
+}
+
+/// Computes the delay until `start_at`, which can be in any of the forms
+/// `StartSpec` accepts.
+pub fn get_start_delay<Tz: TimeZone>(
+    now: DateTime<Tz>,
+    start_at: &str,
+) -> Result<Duration, String> {
+    StartSpec::parse(start_at).map(|spec| spec.delay(&now))
+}
+
+/// When a task should stop running, given as either "YYYY-MM-DD HH:MM" or
+/// just "HH:MM".
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EndTime {
+    /// The first time the clock reads this after the task starts.
+    TimeOfDay(NaiveTime),
+    /// A particular date and time.
+    At(NaiveDateTime),
+}
+
+impl EndTime {
+    pub fn parse(end_at: &str) -> Result<EndTime, String> {
+        if let Ok(time) = NaiveTime::parse_from_str(end_at, "%H:%M") {
+            return Ok(EndTime::TimeOfDay(time));
+        }
+        DATE_TIME_FORMATS
+            .iter()
+            .filter_map(|format| NaiveDateTime::parse_from_str(end_at, format).ok())
+            .map(EndTime::At)
+            .next()
+            .ok_or_else(|| format!("invalid end time: {}", end_at))
+    }
+
+    /// The end time for a task that starts at `start`.
+    pub fn after<Tz: TimeZone>(&self, start: &DateTime<Tz>) -> DateTime<Tz> {
+        let tz = start.timezone();
+        match *self {
+            EndTime::TimeOfDay(time) => {
+                let local = start.naive_local().date().and_time(time);
+                let end = resolve_local(&tz, &local);
+                if end > *start {
+                    end
                 } else {
-                    // The start second has already been reached in the current second, so
-                    // advance to the next minute after the present one, then add the "after"
-                    // value as seconds.
-                    let next = now + chrono::Duration::minutes(1);
-                    Local.ymd(next.year(), next.month(), next.day()).and_hms(
-                        next.hour(),
-                        next.minute(),
-                        after,
-                    )
+                    resolve_local(&tz, &(local + chrono::Duration::days(1)))
                 }
-            };
-            Ok(Duration::from_secs(
-                start_at.signed_duration_since(now).num_seconds().abs() as u64,
-            ))
+            }
+            EndTime::At(local) => resolve_local(&tz, &local),
+        }
+    }
+}
+
+/// The ways in which a duration string such as "1h30m" can be malformed.
+#[derive(Debug, PartialEq)]
+pub enum DurationError {
+    Empty,
+    MissingNumber(String),
+    UnknownUnit(String),
+    Overflow(String),
+}
+
+impl fmt::Display for DurationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DurationError::Empty => write!(f, "empty duration"),
+            DurationError::MissingNumber(ref s) => {
+                write!(f, "invalid duration \"{}\": expected a number", s)
+            }
+            DurationError::UnknownUnit(ref s) => write!(
+                f,
+                "invalid duration \"{}\": unknown unit (use ms, s, m, h or d)",
+                s
+            ),
+            DurationError::Overflow(ref s) => write!(f, "duration \"{}\" is too large", s),
         }
-        None => Err(String::from(format!(
-            "invalid format for start time: {}",
-            next
-        ))),
     }
 }
 
-pub fn get_start_delay_from_hh_mm(start_at: &str) -> Result<Duration, String> {
-    let re = Regex::new(r"(?P<hour>\d{2}):(?P<minute>\d{2})").unwrap();
-    match re.captures(start_at) {
+impl Error for DurationError {}
+
+/// Parses a duration made up of one or more number/unit pairs, such as
+/// "500ms", "90s", "1h30m" or "2d". A bare number is taken as seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, DurationError> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Err(DurationError::Empty);
+    }
+    if let Ok(secs) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(secs));
+    }
+    let overflow = || DurationError::Overflow(value.to_string());
+    let mut total = Duration::from_secs(0);
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        if digits == 0 {
+            return Err(DurationError::MissingNumber(value.to_string()));
+        }
+        let number = rest[..digits].parse::<u64>().map_err(|_| overflow())?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let part = match &rest[..unit_len] {
+            "ms" => Some(Duration::from_millis(number)),
+            "s" => Some(Duration::from_secs(number)),
+            "m" => number.checked_mul(60).map(Duration::from_secs),
+            "h" => number.checked_mul(60 * 60).map(Duration::from_secs),
+            "d" => number.checked_mul(DAY_SECONDS).map(Duration::from_secs),
+            _ => return Err(DurationError::UnknownUnit(value.to_string())),
+        };
+        total = part
+            .and_then(|part| total.checked_add(part))
+            .ok_or_else(overflow)?;
+        rest = &rest[unit_len..];
+    }
+    Ok(total)
+}
+
+/// Picks a random offset between zero and `max`, inclusive.
+pub fn random_offset(max: Duration) -> Duration {
+    let max_nanos = max.as_nanos().min(u64::MAX as u128) as u64;
+    Duration::from_nanos(rand::thread_rng().gen_range(0..=max_nanos))
+}
+
+/// Picks an offset between zero and `max`, inclusive, that depends only on
+/// `key`. The same key always yields the same offset, even across restarts.
+pub fn hashed_offset(key: &str, max: Duration) -> Duration {
+    // FNV-1a, which unlike the standard library's hasher is guaranteed not
+    // to change between releases.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in key.bytes() {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    let max_nanos = max.as_nanos().min(u64::MAX as u128) as u64;
+    Duration::from_nanos(match max_nanos.checked_add(1) {
+        Some(range) => hash % range,
+        None => hash,
+    })
+}
+
+/// A daily range of wall-clock time, such as "09:00-17:00", optionally
+/// limited to certain days of the week, as in "MON-FRI 09:00-17:00". A
+/// range whose end is not after its start, like "22:00-02:00", runs past
+/// midnight, and the days refer to the day on which it begins.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeWindow {
+    spec: String,
+    days_of_week: u64,
+    start_secs: u32,
+    end_secs: u32,
+}
+
+fn parse_window_time(value: &str, allow_end_of_day: bool) -> Result<u32, String> {
+    let re = Regex::new(r"^(?P<hour>\d{2}):(?P<minute>\d{2})$").unwrap();
+    match re.captures(value) {
         Some(time) => {
-            let now = Utc::now();
-            if let Some(start_at) = Local::today().and_hms_opt(
-                (&time["hour"]).parse::<u32>().unwrap(),
-                (&time["minute"]).parse::<u32>().unwrap(),
-                0,
-            ) {
-                let start_delay = match start_at.signed_duration_since(now).num_seconds() {
-                    diff if diff >= 0 => diff as u64,
-                    diff => DAY_SECONDS - (diff.abs() as u64),
-                };
-                Ok(Duration::from_secs(start_delay))
+            let hour = time["hour"].parse::<u32>().unwrap();
+            let minute = time["minute"].parse::<u32>().unwrap();
+            if (hour < 24 && minute < 60) || (allow_end_of_day && hour == 24 && minute == 0) {
+                Ok((hour * 60 + minute) * 60)
             } else {
-                Err(String::from(format!(
-                    "invalid values found in start time: {}",
-                    start_at
-                )))
+                Err(format!("invalid time \"{}\"", value))
+            }
+        }
+        None => Err(format!("invalid time \"{}\", expected HH:MM", value)),
+    }
+}
+
+impl TimeWindow {
+    pub fn parse(spec: &str) -> Result<TimeWindow, String> {
+        let fields: Vec<&str> = spec.split_whitespace().collect();
+        let (days, range) = match fields.len() {
+            1 => ("*", fields[0]),
+            2 => (fields[0], fields[1]),
+            _ => {
+                return Err(format!(
+                    "invalid time window \"{}\", expected \"[DAYS] HH:MM-HH:MM\"",
+                    spec
+                ))
+            }
+        };
+        let window_error = |e: String| format!("invalid time window \"{}\": {}", spec, e);
+        let days_of_week = parse_days_of_week(days).map_err(&window_error)?;
+        let (start, end) = match range.find('-') {
+            Some(pos) => (&range[..pos], &range[pos + 1..]),
+            None => return Err(window_error(String::from("expected HH:MM-HH:MM"))),
+        };
+        Ok(TimeWindow {
+            spec: spec.to_string(),
+            days_of_week,
+            start_secs: parse_window_time(start, false).map_err(&window_error)?,
+            end_secs: parse_window_time(end, true).map_err(&window_error)?,
+        })
+    }
+
+    fn on_day(&self, day: Weekday) -> bool {
+        is_set(self.days_of_week, day.num_days_from_sunday())
+    }
+
+    pub fn contains<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> bool {
+        let secs = now.num_seconds_from_midnight();
+        if self.start_secs < self.end_secs {
+            self.on_day(now.weekday()) && secs >= self.start_secs && secs < self.end_secs
+        } else {
+            (self.on_day(now.weekday()) && secs >= self.start_secs)
+                || (self.on_day(now.weekday().pred()) && secs < self.end_secs)
+        }
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+/// Whether a task may run at `now`: inside one of its active windows (or
+/// any time, if it has none) and outside all of its blackout windows.
+pub fn is_in_windows<Tz: TimeZone>(
+    now: &DateTime<Tz>,
+    active: &[TimeWindow],
+    blackout: &[TimeWindow],
+) -> bool {
+    (active.is_empty() || active.iter().any(|window| window.contains(now)))
+        && !blackout.iter().any(|window| window.contains(now))
+}
+
+/// The conditions, besides its schedule, that decide whether a task may run
+/// at a given time.
+pub struct RunConditions<'a> {
+    pub active_windows: &'a [TimeWindow],
+    pub blackout_windows: &'a [TimeWindow],
+    pub exclude_calendar: Option<&'a Calendar>,
+}
+
+impl<'a> RunConditions<'a> {
+    /// Why a task shouldn't run at `now`, if it shouldn't.
+    pub fn skip_reason<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<String> {
+        if let Some(calendar) = self.exclude_calendar {
+            if let Some(days) = calendar.excludes(now.date_naive()) {
+                return Some(format!("excluded by {} ({})", calendar.name(), days));
+            }
+        }
+        if !self.active_windows.is_empty() && !self.active_windows.iter().any(|w| w.contains(now)) {
+            Some(String::from("outside its active windows"))
+        } else {
+            self.blackout_windows
+                .iter()
+                .find(|w| w.contains(now))
+                .map(|w| format!("in blackout window \"{}\"", w))
+        }
+    }
+}
+
+/// How often a task recurs: on a fixed interval or a cron schedule.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Recurrence {
+    Interval(Duration),
+    Aligned(Duration, Alignment),
+    Cron(CronSchedule),
+}
+
+impl Recurrence {
+    // The first run after `time`.
+    fn next_after(&self, time: DateTime<Zone>) -> Option<DateTime<Zone>> {
+        match *self {
+            Recurrence::Interval(interval) => {
+                time.checked_add_signed(chrono::Duration::from_std(interval).ok()?)
+            }
+            Recurrence::Aligned(interval, alignment) => alignment.next_after(interval, &time),
+            Recurrence::Cron(ref cron) => cron.next_after(time),
+        }
+    }
+}
+
+/// What an aligned interval is counted from: the start of each minute, hour
+/// or day on the clock. A 15 minute interval aligned to the hour runs at
+/// :00, :15, :30 and :45, however long the program has been running.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Alignment {
+    Minute,
+    Hour,
+    Day,
+}
+
+impl Alignment {
+    pub fn parse(value: &str) -> Result<Alignment, String> {
+        match value {
+            "minute" => Ok(Alignment::Minute),
+            "hour" => Ok(Alignment::Hour),
+            "day" => Ok(Alignment::Day),
+            _ => Err(format!(
+                "invalid alignment \"{}\", expected minute, hour or day",
+                value
+            )),
+        }
+    }
+
+    fn period(self) -> chrono::Duration {
+        match self {
+            Alignment::Minute => chrono::Duration::minutes(1),
+            Alignment::Hour => chrono::Duration::hours(1),
+            Alignment::Day => chrono::Duration::days(1),
+        }
+    }
+
+    // The start of the minute, hour or day that `local` is in.
+    fn period_start(self, local: &NaiveDateTime) -> NaiveDateTime {
+        let (hour, minute) = match self {
+            Alignment::Minute => (local.hour(), local.minute()),
+            Alignment::Hour => (local.hour(), 0),
+            Alignment::Day => (0, 0),
+        };
+        local.date().and_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    // The first wall-clock time after `local` that's a whole number of
+    // intervals into its period. The count starts again with each period,
+    // so an interval that doesn't divide evenly into it is cut short.
+    fn naive_next_after(self, interval_nanos: i64, local: &NaiveDateTime) -> Option<NaiveDateTime> {
+        let period_start = self.period_start(local);
+        let elapsed = local
+            .signed_duration_since(period_start)
+            .num_nanoseconds()?;
+        let steps = elapsed / interval_nanos + 1;
+        let next = period_start + chrono::Duration::nanoseconds(steps.checked_mul(interval_nanos)?);
+        Some(next.min(period_start + self.period()))
+    }
+
+    /// The first aligned run strictly after `time`.
+    pub fn next_after<Tz: TimeZone>(
+        self,
+        interval: Duration,
+        time: &DateTime<Tz>,
+    ) -> Option<DateTime<Tz>> {
+        self.next_from(interval, time, false)
+    }
+
+    // The first aligned run after `time`, or at it if `inclusive`.
+    fn next_from<Tz: TimeZone>(
+        self,
+        interval: Duration,
+        time: &DateTime<Tz>,
+        inclusive: bool,
+    ) -> Option<DateTime<Tz>> {
+        let interval_nanos = i64::try_from(interval.as_nanos()).ok()?.max(1);
+        let tz = time.timezone();
+        let mut local = time.naive_local();
+        if inclusive {
+            local -= chrono::Duration::nanoseconds(1);
+        }
+        // When the clocks go back, the first few candidates may resolve to
+        // times that have already passed.
+        for _ in 0..MAX_COUNTED_RUNS {
+            local = self.naive_next_after(interval_nanos, &local)?;
+            let next = resolve_local(&tz, &local);
+            if next > *time || (inclusive && next == *time) {
+                return Some(next);
+            }
+        }
+        None
+    }
+}
+
+/// Keeps track of when a task is next due to run, as judged by a `Clock`.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    recurrence: Recurrence,
+    zone: Zone,
+    next: Option<DateTime<Zone>>,
+}
+
+// A run that starts within this long of its scheduled time is on time.
+// Anything later, such as a run that was due while the host was suspended,
+// counts as missed.
+const LATE_RUN_GRACE_MILLIS: i64 = 1000;
+
+// The longest to wait before checking a schedule against the clock again.
+// Timers run on a monotonic clock, which doesn't advance while the host is
+// suspended and doesn't see the wall clock jump, so a long wait could end
+// well after a run came due.
+const MAX_WAIT: Duration = Duration::from_secs(60);
+
+// Cron schedules are stepped through one run at a time to count missed
+// runs, so the count is capped.
+const MAX_COUNTED_RUNS: u32 = 10000;
+
+/// The runs of a `Schedule` that had come due when it was checked.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DueRuns {
+    /// When the most recent of the due runs was scheduled for.
+    pub scheduled: DateTime<Zone>,
+    /// How many runs were due.
+    pub count: u32,
+    /// How many of those were missed rather than just due: all but the
+    /// most recent, plus that one too if it is more than a second late.
+    pub missed: u32,
+}
+
+impl DueRuns {
+    pub fn on_time(&self) -> bool {
+        self.missed < self.count
+    }
+}
+
+// Making up for every missed run is limited to this many runs, however
+// many were missed, such as after a long suspend with a short interval.
+pub const MAX_CATCH_UP_RUNS: u32 = 100;
+
+/// What to do about runs that were missed, for instance because the host
+/// was suspended or its clock jumped forward.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CatchUp {
+    /// Skip them, and wait for the next scheduled run.
+    None,
+    /// Make up for all of them with a single run.
+    Once,
+    /// Make up each one of them, up to `MAX_CATCH_UP_RUNS`.
+    All,
+}
+
+impl CatchUp {
+    pub fn parse(value: &str) -> Result<CatchUp, String> {
+        match value {
+            "none" => Ok(CatchUp::None),
+            "once" => Ok(CatchUp::Once),
+            "all" => Ok(CatchUp::All),
+            _ => Err(format!(
+                "invalid catch-up policy \"{}\", expected none, once or all",
+                value
+            )),
+        }
+    }
+
+    /// How many times to run a task for the runs in `due`.
+    pub fn runs(self, due: &DueRuns) -> u32 {
+        match self {
+            CatchUp::None if due.on_time() => 1,
+            CatchUp::None => 0,
+            CatchUp::Once => 1,
+            CatchUp::All => due.count.min(MAX_CATCH_UP_RUNS),
+        }
+    }
+}
+
+impl fmt::Display for CatchUp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            CatchUp::None => "none",
+            CatchUp::Once => "once",
+            CatchUp::All => "all",
+        })
+    }
+}
+
+// Counts the runs at due, due + interval, due + 2 * interval and so on that
+// are no later than `now`, without stepping through each one in turn.
+// Returns the count, the last of them and the first run after `now`.
+fn interval_runs_until(
+    due: DateTime<Zone>,
+    now: DateTime<Zone>,
+    interval: Duration,
+) -> Option<(u32, DateTime<Zone>, DateTime<Zone>)> {
+    let interval_nanos = i64::try_from(interval.as_nanos()).ok()?.max(1);
+    let elapsed = now.signed_duration_since(due).num_nanoseconds()?;
+    let count = elapsed / interval_nanos + 1;
+    let latest = due + chrono::Duration::nanoseconds((count - 1).checked_mul(interval_nanos)?);
+    let next = latest + chrono::Duration::nanoseconds(interval_nanos);
+    Some((u32::try_from(count).unwrap_or(u32::MAX), latest, next))
+}
+
+impl Schedule {
+    /// Creates a schedule whose first run is at `start` for an interval, or
+    /// at the first matching time after `start` for a cron expression.
+    pub fn new(recurrence: Recurrence, zone: Zone, start: DateTime<Utc>) -> Schedule {
+        let start = start.with_timezone(&zone);
+        let next = match recurrence {
+            Recurrence::Interval(_) => Some(start),
+            Recurrence::Aligned(interval, alignment) => alignment.next_from(interval, &start, true),
+            Recurrence::Cron(ref cron) => cron.next_after(start),
+        };
+        Schedule {
+            recurrence,
+            zone,
+            next,
+        }
+    }
+
+    pub fn zone(&self) -> Zone {
+        self.zone
+    }
+
+    /// The time of the next run, or `None` if the task won't run again.
+    pub fn next_run(&self) -> Option<DateTime<Zone>> {
+        self.next
+    }
+
+    /// How long until the next run is due: zero if it's due already, or
+    /// `None` if the task won't run again.
+    pub fn delay(&self, clock: &dyn Clock) -> Option<Duration> {
+        self.next
+            .map(|next| delay_until(&self.zone.now(clock), &next))
+    }
+
+    /// How long to wait before checking for due runs: until the next run,
+    /// but no more than a minute, so that runs that came due while the host
+    /// was suspended are noticed soon after it wakes up.
+    pub fn wait(&self, clock: &dyn Clock) -> Option<Duration> {
+        self.delay(clock).map(|delay| delay.min(MAX_WAIT))
+    }
+
+    /// If any runs are due, reports them and moves on to the first
+    /// scheduled time after the present.
+    pub fn take_due(&mut self, clock: &dyn Clock) -> Option<DueRuns> {
+        let now = self.zone.now(clock);
+        let due = match self.next {
+            Some(next) if next <= now => next,
+            _ => return None,
+        };
+        let (count, latest) = match self.recurrence {
+            Recurrence::Interval(interval) => match interval_runs_until(due, now, interval) {
+                Some((count, latest, next)) => {
+                    self.next = Some(next);
+                    (count, latest)
+                }
+                None => {
+                    self.next = None;
+                    (1, due)
+                }
+            },
+            ref recurrence => {
+                let (mut count, mut latest) = (1, due);
+                while count < MAX_COUNTED_RUNS {
+                    match recurrence.next_after(latest) {
+                        Some(run) if run <= now => {
+                            count += 1;
+                            latest = run;
+                        }
+                        _ => break,
+                    }
+                }
+                self.next = recurrence.next_after(now);
+                (count, latest)
+            }
+        };
+        let late = now.signed_duration_since(latest)
+            > chrono::Duration::milliseconds(LATE_RUN_GRACE_MILLIS);
+        Some(DueRuns {
+            scheduled: latest,
+            count,
+            missed: if late { count } else { count - 1 },
+        })
+    }
+
+    /// Starts counting again from the present, so that the next run is an
+    /// interval from now (or the next matching time, for cron). Used when
+    /// the delay between runs is measured from the end of the last one.
+    pub fn restart(&mut self, clock: &dyn Clock) {
+        let now = self.zone.now(clock);
+        self.next = self.recurrence.next_after(now);
+    }
+}
+
+// A preview gives up looking for runs that aren't skipped after this many.
+const MAX_PREVIEWED_RUNS: u32 = 1_000_000;
+
+/// An entry in a preview of a schedule: either a run, or some runs in a row
+/// that would be skipped for the same reason.
+#[derive(Clone, Debug, PartialEq)]
+pub enum PreviewEntry {
+    Run(DateTime<Zone>),
+    Skipped {
+        first: DateTime<Zone>,
+        last: DateTime<Zone>,
+        count: u32,
+        reason: String,
+    },
+}
+
+/// Lists the next `count` runs of `schedule`, along with any runs before
+/// them that `conditions` would skip, stopping early at `end`. Each run is
+/// assumed to take no time, and no jitter is applied.
+pub fn preview_runs(
+    schedule: &Schedule,
+    conditions: &RunConditions,
+    end: Option<DateTime<Utc>>,
+    count: usize,
+) -> Vec<PreviewEntry> {
+    let mut entries = Vec::new();
+    let (mut runs, mut previewed) = (0, 0);
+    let mut next = schedule.next;
+    while let Some(time) = next {
+        if runs == count || previewed == MAX_PREVIEWED_RUNS || end.is_some_and(|end| time >= end) {
+            break;
+        }
+        previewed += 1;
+        match conditions.skip_reason(&time) {
+            None => {
+                entries.push(PreviewEntry::Run(time));
+                runs += 1;
+            }
+            Some(reason) => match entries.last_mut() {
+                Some(&mut PreviewEntry::Skipped {
+                    ref mut last,
+                    ref mut count,
+                    reason: ref last_reason,
+                    ..
+                }) if *last_reason == reason => {
+                    *last = time;
+                    *count += 1;
+                }
+                _ => entries.push(PreviewEntry::Skipped {
+                    first: time,
+                    last: time,
+                    count: 1,
+                    reason,
+                }),
+            },
+        }
+        next = schedule.recurrence.next_after(time);
+    }
+    entries
+}
+
+/// Reads an ISO-8601 date and time, such as "2019-09-02T10:00", as a time
+/// in `zone` unless it has its own UTC offset.
+pub fn parse_date_time_in(value: &str, zone: Zone) -> Result<DateTime<Utc>, String> {
+    match parse_date_time(value) {
+        Some(StartSpec::At(local)) => Ok(resolve_local(&zone, &local).with_timezone(&Utc)),
+        Some(StartSpec::AtInstant(instant)) => Ok(instant.with_timezone(&Utc)),
+        _ => Err(format!("\"{}\" is not an ISO-8601 date and time", value)),
+    }
+}
+
+const MONTH_NAMES: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAY_NAMES: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+// A schedule can go years without firing (e.g. "0 0 29 2 MON"), but one
+// that never fires at all (e.g. "0 0 31 2 *") has to be detected somehow.
+const CRON_SEARCH_YEARS: i32 = 8;
+
+/// A cron expression, in either the standard 5-field form (minute, hour,
+/// day of month, month, day of week) or a 6-field form with a leading
+/// seconds field. Each field is stored as a bitmask of allowed values.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CronSchedule {
+    seconds: u64,
+    minutes: u64,
+    hours: u64,
+    days_of_month: u64,
+    months: u64,
+    days_of_week: u64,
+    // As in traditional cron, when both day fields are restricted a day
+    // matches if either of them does. Otherwise both must match.
+    day_fields_or: bool,
+}
+
+struct CronField {
+    name: &'static str,
+    min: u32,
+    max: u32,
+    names: &'static [&'static str],
+    names_base: u32,
+}
+
+const SECOND_FIELD: CronField = CronField {
+    name: "second",
+    min: 0,
+    max: 59,
+    names: &[],
+    names_base: 0,
+};
+const MINUTE_FIELD: CronField = CronField {
+    name: "minute",
+    min: 0,
+    max: 59,
+    names: &[],
+    names_base: 0,
+};
+const HOUR_FIELD: CronField = CronField {
+    name: "hour",
+    min: 0,
+    max: 23,
+    names: &[],
+    names_base: 0,
+};
+const DAY_OF_MONTH_FIELD: CronField = CronField {
+    name: "day of month",
+    min: 1,
+    max: 31,
+    names: &[],
+    names_base: 0,
+};
+const MONTH_FIELD: CronField = CronField {
+    name: "month",
+    min: 1,
+    max: 12,
+    names: &MONTH_NAMES,
+    names_base: 1,
+};
+// 7 is accepted as an alias for Sunday and folded into 0 by
+// parse_days_of_week().
+const DAY_OF_WEEK_FIELD: CronField = CronField {
+    name: "day of week",
+    min: 0,
+    max: 7,
+    names: &WEEKDAY_NAMES,
+    names_base: 0,
+};
+
+impl CronField {
+    fn value(&self, token: &str) -> Result<u32, String> {
+        let upper = token.to_uppercase();
+        if let Some(pos) = self.names.iter().position(|name| *name == upper) {
+            return Ok(pos as u32 + self.names_base);
+        }
+        match token.parse::<u32>() {
+            Ok(value) if value >= self.min && value <= self.max => Ok(value),
+            Ok(value) => Err(format!(
+                "{} value {} out of range ({}-{})",
+                self.name, value, self.min, self.max
+            )),
+            Err(_) => Err(format!("invalid {} value \"{}\"", self.name, token)),
+        }
+    }
+
+    fn parse(&self, field: &str) -> Result<u64, String> {
+        let mut mask = 0u64;
+        for item in field.split(',') {
+            let (range, step) = match item.find('/') {
+                Some(pos) => {
+                    let step = &item[pos + 1..];
+                    match step.parse::<u32>() {
+                        Ok(step) if step > 0 => (&item[..pos], step),
+                        _ => return Err(format!("invalid {} step \"{}\"", self.name, step)),
+                    }
+                }
+                None => (item, 1),
+            };
+            let (first, last) = if range == "*" {
+                (self.min, self.max)
+            } else if let Some(pos) = range.find('-') {
+                let first = self.value(&range[..pos])?;
+                let last = self.value(&range[pos + 1..])?;
+                if first > last {
+                    return Err(format!("invalid {} range \"{}\"", self.name, range));
+                }
+                (first, last)
+            } else {
+                // "N/step" means "from N to the end of the range, every step".
+                let first = self.value(range)?;
+                (first, if step > 1 { self.max } else { first })
+            };
+            for value in (first..=last).step_by(step as usize) {
+                mask |= 1 << value;
+            }
+        }
+        Ok(mask)
+    }
+}
+
+fn is_set(mask: u64, value: u32) -> bool {
+    mask & (1 << value) != 0
+}
+
+fn parse_days_of_week(field: &str) -> Result<u64, String> {
+    let mask = DAY_OF_WEEK_FIELD.parse(field)?;
+    if is_set(mask, 7) {
+        Ok((mask | 1) & !(1 << 7))
+    } else {
+        Ok(mask)
+    }
+}
+
+impl CronSchedule {
+    pub fn parse(expr: &str) -> Result<CronSchedule, String> {
+        let expanded = match expr.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let (seconds, rest) = match fields.len() {
+            5 => ("0", &fields[..]),
+            6 => (fields[0], &fields[1..]),
+            n => {
+                return Err(format!(
+                    "invalid cron expression \"{}\": expected 5 or 6 fields, found {}",
+                    expr, n
+                ))
+            }
+        };
+        let parse = |field: &CronField, value: &str| {
+            field
+                .parse(value)
+                .map_err(|e| format!("invalid cron expression \"{}\": {}", expr, e))
+        };
+        let days_of_week = parse_days_of_week(rest[4])
+            .map_err(|e| format!("invalid cron expression \"{}\": {}", expr, e))?;
+        Ok(CronSchedule {
+            seconds: parse(&SECOND_FIELD, seconds)?,
+            minutes: parse(&MINUTE_FIELD, rest[0])?,
+            hours: parse(&HOUR_FIELD, rest[1])?,
+            days_of_month: parse(&DAY_OF_MONTH_FIELD, rest[2])?,
+            months: parse(&MONTH_FIELD, rest[3])?,
+            days_of_week,
+            day_fields_or: !rest[2].starts_with('*') && !rest[4].starts_with('*'),
+        })
+    }
+
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let dom = is_set(self.days_of_month, date.day());
+        let dow = is_set(self.days_of_week, date.weekday().num_days_from_sunday());
+        if self.day_fields_or {
+            dom || dow
+        } else {
+            dom && dow
+        }
+    }
+
+    /// Returns the first time strictly after `now` that matches the
+    /// schedule, or `None` if the expression can never match.
+    pub fn next_after<Tz: TimeZone>(&self, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let limit = now.year() + CRON_SEARCH_YEARS;
+        let mut next = now.naive_local().with_nanosecond(0)? + chrono::Duration::seconds(1);
+        loop {
+            if next.year() > limit {
+                return None;
+            }
+            if !is_set(self.months, next.month()) {
+                next = next
+                    .date()
+                    .with_day(1)?
+                    .checked_add_months(chrono::Months::new(1))?
+                    .and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !self.matches_day(next.date()) {
+                next = next.date().succ_opt()?.and_hms_opt(0, 0, 0)?;
+                continue;
+            }
+            if !is_set(self.hours, next.hour()) {
+                next = next.date().and_hms_opt(next.hour(), 0, 0)? + chrono::Duration::hours(1);
+                continue;
+            }
+            if !is_set(self.minutes, next.minute()) {
+                next = next.date().and_hms_opt(next.hour(), next.minute(), 0)?
+                    + chrono::Duration::minutes(1);
+                continue;
+            }
+            if !is_set(self.seconds, next.second()) {
+                next += chrono::Duration::seconds(1);
+                continue;
+            }
+            // Wall-clock times are resolved as described for resolve_local(),
+            // so a time that is repeated when the clocks go back has already
+            // passed the second time around.
+            let time = resolve_local(&now.timezone(), &next);
+            if time > now {
+                return Some(time);
             }
+            next += chrono::Duration::seconds(1);
         }
-        None => Err(String::from(format!(
-            "invalid format for start time: {}",
-            start_at
-        ))),
     }
 }
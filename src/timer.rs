@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+
+use clock::Clock;
+use time::{CatchUp, DueRuns, RunConditions, Schedule};
+
+/// What a task's timer says to do when it's checked.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Tick {
+    /// Nothing is due yet; check again after this long.
+    Wait(Duration),
+    /// Runs have come due, and the task should be run this many times for
+    /// them, one after another.
+    Run { due: DueRuns, runs: u32 },
+    /// The task won't run again, for this reason.
+    Finished(String),
+}
+
+/// Decides, as judged by a `Clock`, when a task runs and when it's
+/// finished: its schedule, catch-up policy, run limit and end time. The
+/// caller does the waiting and starts the invocations.
+#[derive(Clone, Debug)]
+pub struct TaskTimer {
+    schedule: Schedule,
+    catch_up: CatchUp,
+    max_runs: Option<u32>,
+    deadline: Option<DateTime<Utc>>,
+    runs: u32,
+    finished: Option<String>,
+}
+
+impl TaskTimer {
+    pub fn new(
+        schedule: Schedule,
+        catch_up: CatchUp,
+        max_runs: Option<u32>,
+        deadline: Option<DateTime<Utc>>,
+    ) -> TaskTimer {
+        TaskTimer {
+            schedule,
+            catch_up,
+            max_runs,
+            deadline,
+            runs: 0,
+            finished: None,
+        }
+    }
+
+    fn finish(&mut self, reason: String) -> Tick {
+        self.finished = Some(reason.clone());
+        Tick::Finished(reason)
+    }
+
+    /// Checks the schedule against the clock. The end time is checked
+    /// first, and a wait never goes past it.
+    pub fn tick(&mut self, clock: &dyn Clock) -> Tick {
+        if let Some(ref reason) = self.finished {
+            return Tick::Finished(reason.clone());
+        }
+        let now = clock.now();
+        if self.deadline.is_some_and(|deadline| now >= deadline) {
+            return self.finish(String::from("reached its end time"));
+        }
+        if let Some(due) = self.schedule.take_due(clock) {
+            let runs = self.catch_up.runs(&due);
+            return Tick::Run { due, runs };
+        }
+        match (self.schedule.wait(clock), self.deadline) {
+            (Some(wait), Some(deadline)) => Tick::Wait(
+                deadline
+                    .signed_duration_since(now)
+                    .to_std()
+                    .map_or(wait, |until_deadline| wait.min(until_deadline)),
+            ),
+            (Some(wait), None) => Tick::Wait(wait),
+            (None, _) => self.finish(String::from("has no more scheduled runs")),
+        }
+    }
+
+    /// Why one of the runs from a tick shouldn't start now, if it
+    /// shouldn't.
+    pub fn skip_reason(&self, conditions: &RunConditions, clock: &dyn Clock) -> Option<String> {
+        conditions.skip_reason(&self.schedule.zone().now(clock))
+    }
+
+    /// Counts a run that has started. Returns why the task is finished if
+    /// it has now run as many times as it may.
+    pub fn started(&mut self) -> Option<String> {
+        self.runs += 1;
+        if self.max_runs == Some(self.runs) {
+            let reason = format!("has run {} time(s)", self.runs);
+            self.finished = Some(reason.clone());
+            Some(reason)
+        } else {
+            None
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished.is_some()
+    }
+
+    /// Counts the next run from the present. Used when the delay between
+    /// runs is measured from the end of the last one.
+    pub fn restart(&mut self, clock: &dyn Clock) {
+        self.schedule.restart(clock);
+    }
+}